@@ -0,0 +1,59 @@
+use anyhow::{anyhow, Result};
+
+use super::Decoded;
+use crate::backend::{Phase, ProgressFn};
+use crate::settings::LocalAvifSettings;
+
+// ── AVIF 编码 ──────────────────────────────────────────────────
+// 使用 ravif（rav1e），编码较慢，速度档位越高越快、体积越大
+
+#[derive(Clone)]
+pub struct AvifEncoder {
+    quality: f32,
+    speed: u8,
+    alpha_quality: f32,
+}
+
+impl AvifEncoder {
+    pub fn new(settings: &LocalAvifSettings) -> Self {
+        Self {
+            quality: settings.quality.clamp(1, 100) as f32,
+            speed: settings.speed.clamp(1, 10),
+            alpha_quality: settings.alpha_quality.clamp(1, 100) as f32,
+        }
+    }
+
+    pub fn encode(&self, decoded: Decoded, progress: &ProgressFn) -> Result<Vec<u8>> {
+        progress(30, Phase::Processing);
+        let encoder = ravif::Encoder::new()
+            .with_quality(self.quality)
+            .with_alpha_quality(self.alpha_quality)
+            .with_speed(self.speed);
+
+        let (width, height) = (
+            decoded.image.width() as usize,
+            decoded.image.height() as usize,
+        );
+        let encoded = if decoded.image.color().has_alpha() {
+            let pixels: Vec<ravif::RGBA8> = decoded
+                .image
+                .into_rgba8()
+                .pixels()
+                .map(|p| ravif::RGBA8::new(p[0], p[1], p[2], p[3]))
+                .collect();
+            encoder.encode_rgba(ravif::Img::new(&pixels[..], width, height))
+        } else {
+            let pixels: Vec<ravif::RGB8> = decoded
+                .image
+                .into_rgb8()
+                .pixels()
+                .map(|p| ravif::RGB8::new(p[0], p[1], p[2]))
+                .collect();
+            encoder.encode_rgb(ravif::Img::new(&pixels[..], width, height))
+        }
+        .map_err(|e| anyhow!("AVIF 编码失败: {}", e))?;
+        progress(99, Phase::Processing);
+
+        Ok(encoded.avif_file)
+    }
+}
@@ -0,0 +1,72 @@
+use anyhow::{anyhow, bail, Result};
+
+use super::Decoded;
+use crate::backend::{Phase, ProgressFn};
+use crate::error::CompressError;
+use crate::settings::{ChromaSubsampling, LocalJpegSettings};
+
+// ── JPEG 重新编码 ──────────────────────────────────────────────
+// 使用 mozjpeg：默认开启 trellis 量化与哈夫曼表优化，可选渐进式输出
+
+/// JPEG 单边最大像素数（libjpeg 的 JPEG_MAX_DIMENSION）
+const MAX_DIMENSION: u32 = 65500;
+
+#[derive(Clone)]
+pub struct JpegEncoder {
+    quality: f32,
+    subsampling: ChromaSubsampling,
+    progressive: bool,
+}
+
+impl JpegEncoder {
+    pub fn new(settings: &LocalJpegSettings) -> Self {
+        Self {
+            quality: settings.quality.clamp(1, 100) as f32,
+            subsampling: settings.chroma_subsampling.clone(),
+            progressive: settings.progressive,
+        }
+    }
+
+    pub fn encode(&self, decoded: Decoded, progress: &ProgressFn) -> Result<Vec<u8>> {
+        progress(30, Phase::Processing);
+        let rgb = decoded.image.into_rgb8();
+        let (width, height) = rgb.dimensions();
+        let pixels = rgb.into_raw();
+
+        // mozjpeg 通过 panic 报告 libjpeg 错误，release 配置为 panic = "abort" 时会直接退出程序，
+        // 因此能预先判断的错误（尺寸超限）需在调用前拦截
+        if width > MAX_DIMENSION || height > MAX_DIMENSION {
+            bail!(CompressError::UnsupportedFormat(format!(
+                "JPEG 最大支持 {} × {} 像素，图片为 {} × {}",
+                MAX_DIMENSION, MAX_DIMENSION, width, height
+            )));
+        }
+
+        let mut comp = mozjpeg::Compress::new(mozjpeg::ColorSpace::JCS_RGB);
+        comp.set_size(width as usize, height as usize);
+        comp.set_quality(self.quality);
+        comp.set_optimize_coding(true);
+        if self.progressive {
+            comp.set_progressive_mode();
+        }
+        let (cb, cr) = match self.subsampling {
+            ChromaSubsampling::Yuv420 => ((2, 2), (2, 2)),
+            ChromaSubsampling::Yuv422 => ((2, 1), (2, 1)),
+            ChromaSubsampling::Yuv444 => ((1, 1), (1, 1)),
+        };
+        comp.set_chroma_sampling_pixel_sizes(cb, cr);
+
+        let encode = || -> std::io::Result<Vec<u8>> {
+            let mut started = comp.start_compress(Vec::new())?;
+            if let Some(icc) = &decoded.icc_profile {
+                started.write_icc_profile(icc);
+            }
+            started.write_scanlines(&pixels)?;
+            started.finish()
+        };
+        let data = encode().map_err(|e| anyhow!("JPEG 编码失败: {}", e))?;
+        progress(99, Phase::Processing);
+
+        Ok(data)
+    }
+}
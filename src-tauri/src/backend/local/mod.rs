@@ -0,0 +1,228 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use image::{DynamicImage, ImageDecoder};
+use std::path::{Path, PathBuf};
+
+use super::{BackendOutput, CompressOptions, CompressionBackend, Phase, ProgressFn};
+use crate::error::CompressError;
+use crate::format::ImageFormat;
+use crate::settings::{AppSettings, ResizeMethod, ResizeSpec};
+
+mod avif;
+mod jpeg;
+mod png;
+mod webp;
+
+// ── 本地后端 ───────────────────────────────────────────────────
+// 完全离线压缩，不消耗 TinyPNG 配额；按输入格式分派到具体编码器。
+// 编解码是 CPU 密集操作，放到阻塞线程池执行，避免卡住异步运行时；
+// 解码需要完整的原图，本地后端无法流式处理
+
+#[derive(Clone)]
+pub struct LocalBackend {
+    png: png::PngEncoder,
+    jpeg: jpeg::JpegEncoder,
+    webp: webp::WebpEncoder,
+    avif: avif::AvifEncoder,
+}
+
+impl LocalBackend {
+    pub fn new(settings: &AppSettings) -> Self {
+        Self {
+            png: png::PngEncoder::new(&settings.local_png),
+            jpeg: jpeg::JpegEncoder::new(&settings.local_jpeg),
+            webp: webp::WebpEncoder::new(&settings.local_webp),
+            avif: avif::AvifEncoder::new(&settings.local_avif),
+        }
+    }
+}
+
+#[async_trait]
+impl CompressionBackend for LocalBackend {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn check_input(&self, header: &[u8], options: &CompressOptions) -> Result<()> {
+        check_format(header, options.format)?;
+        if options.output_format() == ImageFormat::Jpeg {
+            parse_background(&options.background)?;
+        }
+        Ok(())
+    }
+
+    async fn compress(
+        &self,
+        input: &Path,
+        output: &Path,
+        options: &CompressOptions,
+        progress: &ProgressFn,
+    ) -> Result<BackendOutput> {
+        let backend = self.clone();
+        let (input, output) = (input.to_path_buf(), output.to_path_buf());
+        let options = options.clone();
+        let progress = progress.clone();
+        tokio::task::spawn_blocking(move || {
+            backend.compress_blocking(input, output, &options, &progress)
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("本地压缩任务异常退出: {}", e))?
+    }
+}
+
+impl LocalBackend {
+    fn compress_blocking(
+        &self,
+        input: PathBuf,
+        output: PathBuf,
+        options: &CompressOptions,
+        progress: &ProgressFn,
+    ) -> Result<BackendOutput> {
+        // 未指定转换格式时按原格式重新压缩
+        let target = options.output_format();
+
+        progress(0, Phase::Processing);
+        let input = std::fs::read(&input)
+            .map_err(|e| CompressError::FileRead(format!("读取文件失败: {}", e)))?;
+        self.check_input(&input, options)?;
+        let background = match target {
+            ImageFormat::Jpeg => Some(parse_background(&options.background)?),
+            _ => None,
+        };
+        let mut decoded = decode(&input, options.format)?;
+        drop(input);
+        if let Some(bg) = background {
+            decoded.image = flatten(decoded.image, bg);
+        }
+        if let Some(spec) = &options.resize {
+            decoded.image = resize(decoded.image, spec);
+        }
+        let (width, height) = (decoded.image.width(), decoded.image.height());
+        // 编码无法中途打断，只在耗时的编码开始前检查取消
+        options.cancel.check()?;
+        progress(10, Phase::Processing);
+
+        let data = match target {
+            ImageFormat::Png => self.png.encode(decoded, progress),
+            ImageFormat::Jpeg => self.jpeg.encode(decoded, progress),
+            ImageFormat::Webp => self.webp.encode(decoded, progress),
+            ImageFormat::Avif => self.avif.encode(decoded, progress),
+        }?;
+        std::fs::write(&output, &data)
+            .map_err(|e| CompressError::DiskWrite(format!("写入临时文件失败: {}", e)))?;
+
+        Ok(BackendOutput {
+            size: data.len() as u64,
+            format: target,
+            width: Some(width),
+            height: Some(height),
+            preserved: Vec::new(),
+            compression_count: None,
+            api_key: None,
+        })
+    }
+}
+
+// ── 解码 ───────────────────────────────────────────────────────
+
+/// 解码后的图片，方向已按 EXIF 校正
+pub struct Decoded {
+    pub image: DynamicImage,
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+/// 按文件头检查能否解码，header 至少包含前 21 字节
+fn check_format(header: &[u8], format: ImageFormat) -> Result<()> {
+    match format {
+        // AVIF 解码依赖系统 dav1d，本地后端只支持输出 AVIF
+        ImageFormat::Avif => bail!(CompressError::UnsupportedFormat(
+            "本地压缩暂不支持读取 AVIF，请使用 TinyPNG 后端".to_string()
+        )),
+        // 只会解码第一帧，动画会被静默丢弃，因此直接拒绝
+        ImageFormat::Webp if is_animated_webp(header) => bail!(CompressError::UnsupportedFormat(
+            "暂不支持压缩动画 WebP".to_string()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// 解码原图，调用前需已通过 check_format
+fn decode(input: &[u8], format: ImageFormat) -> Result<Decoded> {
+    let image_format = match format {
+        ImageFormat::Png => image::ImageFormat::Png,
+        ImageFormat::Jpeg => image::ImageFormat::Jpeg,
+        ImageFormat::Webp => image::ImageFormat::WebP,
+        ImageFormat::Avif => image::ImageFormat::Avif,
+    };
+    let mut decoder = image::ImageReader::with_format(std::io::Cursor::new(input), image_format)
+        .into_decoder()
+        .map_err(|e| CompressError::UnsupportedFormat(format!("图片解码失败: {}", e)))?;
+
+    // 重新编码会丢弃 EXIF，需先把旋转方向应用到像素上
+    let icc_profile = decoder.icc_profile().ok().flatten();
+    let orientation = decoder
+        .orientation()
+        .unwrap_or(image::metadata::Orientation::NoTransforms);
+    let mut image = DynamicImage::from_decoder(decoder)
+        .map_err(|e| CompressError::UnsupportedFormat(format!("图片解码失败: {}", e)))?;
+    image.apply_orientation(orientation);
+
+    Ok(Decoded { image, icc_profile })
+}
+
+/// VP8X 扩展头的 flags 字节中第 2 位表示动画
+fn is_animated_webp(data: &[u8]) -> bool {
+    data.len() > 20 && &data[12..16] == b"VP8X" && data[20] & 0x02 != 0
+}
+
+/// 解析背景色：white / black / #rrggbb
+fn parse_background(value: &str) -> Result<[u8; 3]> {
+    let value = value.trim().to_lowercase();
+    match value.as_str() {
+        "white" => return Ok([255, 255, 255]),
+        "black" => return Ok([0, 0, 0]),
+        _ => {}
+    }
+    let hex = value.strip_prefix('#').unwrap_or(&value);
+    if hex.len() == 6 {
+        if let Ok(v) = u32::from_str_radix(hex, 16) {
+            return Ok([(v >> 16) as u8, (v >> 8) as u8, v as u8]);
+        }
+    }
+    bail!(CompressError::InvalidSettings(format!(
+        "无效的背景色: {}",
+        value
+    )))
+}
+
+/// 把透明图片按 alpha 混合到纯色背景上
+fn flatten(image: DynamicImage, bg: [u8; 3]) -> DynamicImage {
+    if !image.color().has_alpha() {
+        return image;
+    }
+    let mut rgba = image.into_rgba8();
+    for px in rgba.pixels_mut() {
+        let a = px[3] as u32;
+        for c in 0..3 {
+            px[c] = ((px[c] as u32 * a + bg[c] as u32 * (255 - a) + 127) / 255) as u8;
+        }
+        px[3] = 255;
+    }
+    DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).into_rgb8())
+}
+
+/// 按 TinyPNG 的缩放语义调整尺寸（见 ResizeSpec::target_size）；
+/// cover / thumb 居中裁剪（TinyPNG 会智能识别主体，本地无法做到）
+fn resize(image: DynamicImage, spec: &ResizeSpec) -> DynamicImage {
+    use image::imageops::FilterType;
+
+    let (w, h) = (image.width(), image.height());
+    let (tw, th) = spec.target_size(w, h);
+    match spec.method {
+        ResizeMethod::Scale | ResizeMethod::Fit if (tw, th) == (w, h) => image,
+        ResizeMethod::Scale | ResizeMethod::Fit => image.resize_exact(tw, th, FilterType::Lanczos3),
+        ResizeMethod::Cover | ResizeMethod::Thumb => {
+            image.resize_to_fill(tw, th, FilterType::Lanczos3)
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+
+use super::Decoded;
+use crate::backend::{Phase, ProgressFn};
+use crate::settings::LocalPngSettings;
+
+// ── PNG 有损压缩 ───────────────────────────────────────────────
+// 与 TinyPNG 服务端思路一致：先把真彩色量化为 ≤256 色调色板，
+// 再交给 oxipng 做位深缩减、滤波选择和 deflate 重新压缩
+
+#[derive(Clone)]
+pub struct PngEncoder {
+    max_colors: usize,
+    sample_factor: i32,
+    optimize_level: u8,
+}
+
+impl PngEncoder {
+    pub fn new(settings: &LocalPngSettings) -> Self {
+        Self {
+            max_colors: settings.max_colors.clamp(2, 256) as usize,
+            sample_factor: settings.quantize_speed.clamp(1, 30) as i32,
+            optimize_level: settings.optimize_level.min(6),
+        }
+    }
+
+    pub fn encode(&self, decoded: Decoded, progress: &ProgressFn) -> Result<Vec<u8>> {
+        let rgba = decoded.image.into_rgba8();
+        let (width, height) = rgba.dimensions();
+        let pixels = rgba.into_raw();
+
+        // ── 量化阶段 (10-60%) ───────────────────────────────────
+        progress(10, Phase::Processing);
+        let (palette, indices) = self.quantize(&pixels, progress);
+        progress(60, Phase::Processing);
+
+        // ── 重新压缩阶段 (60-99%) ───────────────────────────────
+        let mut raw = oxipng::RawImage::new(
+            width,
+            height,
+            oxipng::ColorType::Indexed { palette },
+            oxipng::BitDepth::Eight,
+            indices,
+        )
+        .map_err(|e| anyhow!("构建 PNG 数据失败: {}", e))?;
+        if let Some(icc) = &decoded.icc_profile {
+            raw.add_icc_profile(icc);
+        }
+        let data = raw
+            .create_optimized_png(&oxipng::Options::from_preset(self.optimize_level))
+            .map_err(|e| anyhow!("PNG 优化失败: {}", e))?;
+        progress(99, Phase::Processing);
+
+        Ok(data)
+    }
+
+    /// 量化为调色板 + 索引；颜色数本身不超过上限时直接无损建表。
+    /// 训练调色板和逐像素映射耗时相近，调色板训练完成时上报一次进度
+    fn quantize(&self, pixels: &[u8], progress: &ProgressFn) -> (Vec<oxipng::RGBA8>, Vec<u8>) {
+        let mut unique = HashSet::new();
+        for px in pixels.chunks_exact(4) {
+            unique.insert([px[0], px[1], px[2], px[3]]);
+            if unique.len() > self.max_colors {
+                break;
+            }
+        }
+
+        if unique.len() <= self.max_colors {
+            let palette: Vec<[u8; 4]> = unique.into_iter().collect();
+            let lookup: std::collections::HashMap<[u8; 4], u8> = palette
+                .iter()
+                .enumerate()
+                .map(|(i, c)| (*c, i as u8))
+                .collect();
+            let indices = pixels
+                .chunks_exact(4)
+                .map(|px| lookup[&[px[0], px[1], px[2], px[3]]])
+                .collect();
+            return (palette.into_iter().map(to_rgba8).collect(), indices);
+        }
+
+        let nq = color_quant::NeuQuant::new(self.sample_factor, self.max_colors, pixels);
+        let palette = nq
+            .color_map_rgba()
+            .chunks_exact(4)
+            .map(|c| to_rgba8([c[0], c[1], c[2], c[3]]))
+            .collect();
+        progress(35, Phase::Processing);
+        let indices = pixels
+            .chunks_exact(4)
+            .map(|px| nq.index_of(px) as u8)
+            .collect();
+        (palette, indices)
+    }
+}
+
+fn to_rgba8(c: [u8; 4]) -> oxipng::RGBA8 {
+    oxipng::RGBA8::new(c[0], c[1], c[2], c[3])
+}
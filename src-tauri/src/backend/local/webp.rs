@@ -0,0 +1,58 @@
+use anyhow::{anyhow, Result};
+
+use super::Decoded;
+use crate::backend::{Phase, ProgressFn};
+use crate::settings::LocalWebpSettings;
+
+// ── WebP 编码 ──────────────────────────────────────────────────
+// 使用 libwebp，支持有损/无损；既可重新压缩 WebP，也可作为 PNG/JPEG 的转换目标
+
+#[derive(Clone)]
+pub struct WebpEncoder {
+    lossless: bool,
+    quality: f32,
+    method: i32,
+    alpha_quality: i32,
+}
+
+impl WebpEncoder {
+    pub fn new(settings: &LocalWebpSettings) -> Self {
+        Self {
+            lossless: settings.lossless,
+            quality: settings.quality.min(100) as f32,
+            method: settings.method.min(6) as i32,
+            alpha_quality: settings.alpha_quality.min(100) as i32,
+        }
+    }
+
+    pub fn encode(&self, decoded: Decoded, progress: &ProgressFn) -> Result<Vec<u8>> {
+        progress(30, Phase::Processing);
+        let has_alpha = decoded.image.color().has_alpha();
+        let (width, height) = (decoded.image.width(), decoded.image.height());
+        // 不透明图片按 RGB 编码，省去一个无用的 alpha 平面
+        let pixels = if has_alpha {
+            decoded.image.into_rgba8().into_raw()
+        } else {
+            decoded.image.into_rgb8().into_raw()
+        };
+        let encoder = if has_alpha {
+            webp::Encoder::from_rgba(&pixels, width, height)
+        } else {
+            webp::Encoder::from_rgb(&pixels, width, height)
+        };
+
+        let mut config = webp::WebPConfig::new().map_err(|_| anyhow!("初始化 WebP 编码器失败"))?;
+        config.lossless = self.lossless as i32;
+        config.quality = self.quality;
+        config.method = self.method;
+        config.alpha_quality = self.alpha_quality;
+
+        let data = encoder
+            .encode_advanced(&config)
+            .map_err(|e| anyhow!("WebP 编码失败: {:?}", e))?
+            .to_vec();
+        progress(99, Phase::Processing);
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::cancel::CancelToken;
+use crate::error::CompressError;
+use crate::format::ImageFormat;
+use crate::settings::{ApiKey, AppSettings, BackendKind, MetadataField, ResizeSpec};
+
+mod local;
+mod retry;
+mod throttle;
+mod tinypng;
+
+pub use local::LocalBackend;
+pub use tinypng::{KeyState, KeyStatus, TinyPngBackend};
+
+// ── 压缩后端 ───────────────────────────────────────────────────
+// 后端只负责「读源文件 → 写临时文件」，输出路径、进度事件、原子替换
+// 等通用流程由 compress 模块统一处理；网络后端全程流式读写，内存占用与图片大小无关
+
+/// 压缩阶段，对应 compress-progress 事件中的 phase 字段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Uploading,
+    Processing,
+    Downloading,
+    /// 网络临时错误，等待 delay_ms 毫秒后进行第 attempt 次重试
+    Retrying {
+        attempt: u32,
+        delay_ms: u64,
+    },
+}
+
+impl Phase {
+    /// 事件中 phase 字段的取值
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Uploading => "uploading",
+            Self::Processing => "processing",
+            Self::Downloading => "downloading",
+            Self::Retrying { .. } => "retrying",
+        }
+    }
+}
+
+/// 进度回调：(总进度百分比 0-99, 当前阶段)
+pub type ProgressFn = Arc<dyn Fn(u8, Phase) + Send + Sync>;
+
+/// 单次压缩的参数
+#[derive(Debug, Clone)]
+pub struct CompressOptions {
+    /// 输入图片格式
+    pub format: ImageFormat,
+    /// 输出格式，None 表示保持原格式
+    pub convert_to: Option<ImageFormat>,
+    /// 透明图片转为 JPEG 时填充的背景色（white / black / #rrggbb）
+    pub background: String,
+    /// 缩放参数，None 表示保持原尺寸
+    pub resize: Option<ResizeSpec>,
+    /// 需要保留的元数据
+    pub preserve: Vec<MetadataField>,
+    /// 取消标记，后端在传输和各阶段之间检查
+    pub cancel: CancelToken,
+}
+
+impl CompressOptions {
+    /// 实际输出格式
+    pub fn output_format(&self) -> ImageFormat {
+        self.convert_to.unwrap_or(self.format)
+    }
+}
+
+/// 后端压缩结果
+#[derive(Debug)]
+pub struct BackendOutput {
+    /// 写入输出文件的字节数
+    pub size: u64,
+    pub format: ImageFormat,
+    /// 输出图片尺寸，后端无法得知时为 None
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// 实际保留下来的元数据
+    pub preserved: Vec<MetadataField>,
+    /// TinyPNG 本月已压缩次数，本地后端为 None
+    pub compression_count: Option<u32>,
+    /// 实际使用的 API Key（多个 Key 轮换时不一定是第一个），本地后端为 None
+    pub api_key: Option<ApiKey>,
+}
+
+#[async_trait]
+pub trait CompressionBackend: Send + Sync {
+    /// 后端标识，写入 CompressResult 供前端展示
+    fn name(&self) -> &'static str;
+
+    /// 按文件头检查能否处理该输入，不能时返回与实际压缩相同的错误；
+    /// 预估时据此提前标出会失败的文件。默认全部支持
+    fn check_input(&self, _header: &[u8], _options: &CompressOptions) -> Result<()> {
+        Ok(())
+    }
+
+    /// 从 input 读取原图，把压缩结果写入 output
+    async fn compress(
+        &self,
+        input: &Path,
+        output: &Path,
+        options: &CompressOptions,
+        progress: &ProgressFn,
+    ) -> Result<BackendOutput>;
+}
+
+/// 根据设置创建对应的压缩后端
+pub fn from_settings(settings: &AppSettings) -> Result<Arc<dyn CompressionBackend>> {
+    match settings.backend {
+        BackendKind::TinyPng => {
+            let keys = settings.active_keys();
+            if keys.is_empty() {
+                bail!(CompressError::Unauthorized(
+                    "API Key 未配置，请在设置中填写 TinyPNG API Key".to_string()
+                ));
+            }
+            Ok(Arc::new(TinyPngBackend::new(
+                keys,
+                &settings.api_endpoint,
+                settings.retry.clone(),
+                &settings.proxy,
+                &settings.network,
+            )?))
+        }
+        BackendKind::Local => Ok(Arc::new(LocalBackend::new(settings))),
+    }
+}
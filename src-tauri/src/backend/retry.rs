@@ -0,0 +1,103 @@
+use anyhow::{anyhow, Error, Result};
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+use crate::cancel::CancelToken;
+use crate::error::CompressError;
+use crate::settings::RetrySettings;
+
+// ── 失败重试 ───────────────────────────────────────────────────
+// 连接中断、超时、5xx 等临时错误按指数退避重试；
+// Key 无效、格式不支持等重试也不会成功的错误立即返回
+
+/// 单次尝试失败的原因
+pub enum Failure {
+    /// 临时错误，可以重试
+    Transient(Error),
+    /// 重试也不会成功
+    Fatal(Error),
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Self {
+        Failure::Fatal(e)
+    }
+}
+
+impl From<CompressError> for Failure {
+    fn from(e: CompressError) -> Self {
+        Failure::Fatal(e.into())
+    }
+}
+
+/// 按策略执行 attempt，临时错误时等待后重试；
+/// 每次重试前调用 on_retry(第几次重试, 等待时间)。
+/// 请求和等待都与取消标记竞争，任务被取消后立即返回
+pub async fn run<T, F, Fut>(
+    policy: &RetrySettings,
+    cancel: &CancelToken,
+    mut on_retry: impl FnMut(u32, Duration),
+    mut attempt: F,
+) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, Failure>>,
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut n = 1;
+    loop {
+        cancel.check()?;
+        let result = tokio::select! {
+            result = attempt() => result,
+            _ = cancel.cancelled() => return Err(CompressError::cancelled().into()),
+        };
+        match result {
+            Ok(value) => return Ok(value),
+            Err(Failure::Fatal(e)) => return Err(e),
+            Err(Failure::Transient(e)) if n >= max_attempts => {
+                return Err(if max_attempts > 1 {
+                    with_retry_count(e, max_attempts - 1)
+                } else {
+                    e
+                });
+            }
+            Err(Failure::Transient(_)) => {
+                let delay = backoff(policy, n);
+                on_retry(n, delay);
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = cancel.cancelled() => return Err(CompressError::cancelled().into()),
+                }
+                n += 1;
+            }
+        }
+    }
+}
+
+/// 在错误信息后注明重试次数，保留错误类别
+fn with_retry_count(e: Error, retries: u32) -> Error {
+    let message = format!("{}（已重试 {} 次）", e, retries);
+    match e.downcast_ref::<CompressError>() {
+        Some(err) => err.with_message(message).into(),
+        None => anyhow!(message),
+    }
+}
+
+/// 第 n 次重试前的等待时间：base * 2^(n-1)，不超过上限；
+/// 开启抖动时在 [一半, 全部] 之间随机取值
+fn backoff(policy: &RetrySettings, n: u32) -> Duration {
+    let exp = policy.base_delay_ms.saturating_mul(1u64 << (n - 1).min(20));
+    let mut ms = exp.min(policy.max_delay_ms);
+    if policy.jitter && ms > 1 {
+        let half = ms / 2;
+        ms = half + random() % (ms - half + 1);
+    }
+    Duration::from_millis(ms)
+}
+
+/// 不引入 rand 依赖：RandomState 每次构造都带随机种子
+fn random() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
@@ -0,0 +1,34 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// ── 限速 ───────────────────────────────────────────────────────
+// 同方向的所有并发传输共享一条时间线：每块数据按限速折算成占用时长，
+// 依次排在前一块之后，总带宽不超过设定值
+
+pub struct Throttle(Mutex<Option<Instant>>);
+
+pub static UPLOAD: Throttle = Throttle::new();
+pub static DOWNLOAD: Throttle = Throttle::new();
+
+impl Throttle {
+    const fn new() -> Self {
+        Self(Mutex::new(None))
+    }
+
+    /// 每传输一块数据调用一次，超出限速时等待轮到自己；
+    /// limit 为每秒字节数，0 表示不限速
+    pub async fn pace(&self, bytes: usize, limit: u64) {
+        if limit == 0 {
+            return;
+        }
+        let start = {
+            let mut next = self.0.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            // 空闲后重新从当前时间开始排，不累积之前未用的额度
+            let start = next.map_or(now, |t| t.max(now));
+            *next = Some(start + Duration::from_secs_f64(bytes as f64 / limit as f64));
+            start
+        };
+        tokio::time::sleep_until(start.into()).await;
+    }
+}
@@ -0,0 +1,874 @@
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::Notify;
+
+use crate::error::CompressError;
+use crate::format::ImageFormat;
+use crate::settings::{
+    ApiKey, MetadataField, NetworkSettings, ProxyMode, ProxySettings, RetrySettings,
+};
+use crate::usage;
+
+use super::retry::{self, Failure};
+use super::throttle;
+use super::{BackendOutput, CompressOptions, CompressionBackend, Phase, ProgressFn};
+
+// ── HTTP Client ────────────────────────────────────────────────
+// 全局共享 Client：避免每次压缩都重建 TLS 上下文和连接池；
+// 代理或连接超时设置变化后，下次创建后端时按新设置重建。
+// 请求总超时按请求设置（见 TinyPngBackend::timeout），限速的传输不受其限制，
+// 改由空闲超时（见 Activity）发现卡住的连接
+
+/// 影响 Client 构建的设置
+#[derive(Clone, PartialEq)]
+struct ClientConfig {
+    proxy: ProxySettings,
+    connect_timeout_secs: u64,
+}
+
+static HTTP_CLIENT: Mutex<Option<(ClientConfig, reqwest::Client)>> = Mutex::new(None);
+
+fn client(proxy: &ProxySettings, network: &NetworkSettings) -> Result<reqwest::Client> {
+    let config = ClientConfig {
+        proxy: proxy.clone(),
+        connect_timeout_secs: network.connect_timeout_secs,
+    };
+    let mut cached = HTTP_CLIENT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((cached_config, client)) = cached.as_ref() {
+        if *cached_config == config {
+            return Ok(client.clone());
+        }
+    }
+    let mut builder = reqwest::Client::builder().pool_max_idle_per_host(4);
+    if config.connect_timeout_secs > 0 {
+        builder = builder.connect_timeout(Duration::from_secs(config.connect_timeout_secs));
+    }
+    builder = match proxy.mode {
+        // reqwest 默认读取系统代理和环境变量
+        ProxyMode::System => builder,
+        ProxyMode::None => builder.no_proxy(),
+        ProxyMode::Manual => builder.proxy(manual_proxy(proxy)?),
+    };
+    let client = builder
+        .build()
+        .map_err(|e| anyhow!("构建 HTTP Client 失败: {}", e))?;
+    *cached = Some((config, client.clone()));
+    Ok(client)
+}
+
+/// 手动代理：认证信息写进 URL，HTTP 代理和 SOCKS5 代理都能识别
+fn manual_proxy(settings: &ProxySettings) -> Result<reqwest::Proxy> {
+    let url = settings.url.trim();
+    if url.is_empty() {
+        bail!(CompressError::InvalidSettings("代理地址未填写".to_string()));
+    }
+    let invalid = |reason: String| {
+        CompressError::InvalidSettings(format!("代理地址无效 {}: {}", url, reason))
+    };
+    // 省略协议时按 HTTP 代理处理
+    let full = if url.contains("://") {
+        url.to_string()
+    } else {
+        format!("http://{}", url)
+    };
+    let mut proxy_url = reqwest::Url::parse(&full).map_err(|e| invalid(e.to_string()))?;
+    if !matches!(proxy_url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(invalid(format!("不支持的代理协议 {}", proxy_url.scheme())).into());
+    }
+    if !settings.username.is_empty() {
+        proxy_url
+            .set_username(&settings.username)
+            .and_then(|_| proxy_url.set_password(Some(&settings.password)))
+            .map_err(|_| invalid("无法附加认证信息".to_string()))?;
+    }
+    let proxy = reqwest::Proxy::all(proxy_url).map_err(|e| invalid(e.to_string()))?;
+    Ok(proxy.no_proxy(reqwest::NoProxy::from_string(&settings.no_proxy)))
+}
+
+// ── 数据结构 ───────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+struct TinyPngOutput {
+    url: String,
+    size: u64,
+    #[serde(default)]
+    width: Option<u32>,
+    #[serde(default)]
+    height: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TinyPngResponse {
+    output: TinyPngOutput,
+    #[allow(dead_code)]
+    input: TinyPngInput,
+}
+
+#[derive(Debug, Deserialize)]
+struct TinyPngInput {
+    #[allow(dead_code)]
+    size: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TinyPngError {
+    #[serde(default)]
+    error: String,
+    message: String,
+}
+
+/// 一次上传（/shrink）的结果
+enum Shrink {
+    Done {
+        response: TinyPngResponse,
+        compression_count: Option<u32>,
+    },
+    /// 该 Key 本月额度已用完，可换下一个 Key 重试
+    LimitReached(String),
+}
+
+/// 下载到的压缩结果
+struct Downloaded {
+    size: u64,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+/// API Key 校验结论
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum KeyState {
+    Valid,
+    Invalid,
+    /// Key 有效但本月额度已用完
+    LimitReached,
+    /// 无法连接服务或服务异常，Key 是否有效未知
+    NetworkError,
+}
+
+/// API Key 校验结果，供设置页展示
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyStatus {
+    pub state: KeyState,
+    /// 本月已压缩次数，服务端未返回时为 None
+    pub compression_count: Option<u32>,
+    pub message: String,
+}
+
+// ── 空闲检测 ───────────────────────────────────────────────────
+// 超过设定时间没有任何数据进出即判定连接卡住，按临时错误重试。
+// 按限速主动等待的时间不算空闲，慢速但仍在传输的连接不会被中断
+
+/// 上传的传输活动：上传 Body 每被读取一块调用 touch
+#[derive(Default)]
+struct Activity {
+    notify: Notify,
+    /// 正在按限速等待
+    pacing: AtomicBool,
+}
+
+impl Activity {
+    fn touch(&self) {
+        self.notify.notify_one();
+    }
+
+    /// 超过 idle 没有活动时返回；idle 为 None 时永不返回
+    async fn stalled(&self, idle: Option<Duration>) {
+        let Some(idle) = idle else {
+            return std::future::pending().await;
+        };
+        loop {
+            let timed_out = tokio::time::timeout(idle, self.notify.notified())
+                .await
+                .is_err();
+            if timed_out && !self.pacing.load(Ordering::Relaxed) {
+                return;
+            }
+        }
+    }
+}
+
+// ── 上传 Body ──────────────────────────────────────────────────
+// 边读源文件边上传，内存中最多只有一块；每读出一块回报一次上传百分比
+
+const UPLOAD_CHUNK: usize = 64 * 1024;
+
+fn upload_body(
+    file: tokio::fs::File,
+    total: u64,
+    limit: u64,
+    progress: ProgressFn,
+    activity: Arc<Activity>,
+) -> reqwest::Body {
+    let chunks = futures_util::stream::try_unfold(
+        (file, progress, activity, 0u64, 0u8),
+        move |(mut file, progress, activity, mut sent, mut last_pct)| async move {
+            // 被读取说明上一块已交给连接
+            activity.touch();
+            let mut buf = vec![0u8; UPLOAD_CHUNK];
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(None);
+            }
+            buf.truncate(n);
+            activity.pacing.store(true, Ordering::Relaxed);
+            throttle::UPLOAD.pace(n, limit).await;
+            activity.pacing.store(false, Ordering::Relaxed);
+            activity.touch();
+            sent += n as u64;
+            // 上传占总进度的 0-40%
+            let pct = (sent as f64 / total.max(1) as f64 * 40.0) as u8;
+            if pct > last_pct {
+                last_pct = pct;
+                progress(pct, Phase::Uploading);
+            }
+            let state = (file, progress, activity, sent, last_pct);
+            Ok::<_, std::io::Error>(Some((Bytes::from(buf), state)))
+        },
+    );
+    reqwest::Body::wrap_stream(chunks)
+}
+
+/// 只上报不低于之前的百分比，阶段照常更新
+fn monotonic(progress: ProgressFn) -> ProgressFn {
+    let highest = Arc::new(AtomicU8::new(0));
+    Arc::new(move |pct, phase| {
+        let pct = highest.fetch_max(pct, Ordering::Relaxed).max(pct);
+        progress(pct, phase)
+    })
+}
+
+fn retrying(attempt: u32, delay: Duration) -> Phase {
+    Phase::Retrying {
+        attempt,
+        delay_ms: delay.as_millis() as u64,
+    }
+}
+
+// ── 用量记录 ───────────────────────────────────────────────────
+// usage.json 的读写是阻塞文件 I/O，放到阻塞线程池执行；记录失败不影响本次压缩
+
+async fn record_usage(api_key: &str, count: u32) {
+    let api_key = api_key.to_string();
+    tokio::task::spawn_blocking(move || usage::record(&api_key, count))
+        .await
+        .ok();
+}
+
+async fn set_exhausted(api_key: &str, exhausted: bool) {
+    let api_key = api_key.to_string();
+    tokio::task::spawn_blocking(move || usage::set_exhausted(&api_key, exhausted))
+        .await
+        .ok();
+}
+
+// ── TinyPNG 后端 ───────────────────────────────────────────────
+
+pub struct TinyPngBackend {
+    /// 按轮换顺序排列的 Key
+    keys: Vec<ApiKey>,
+    endpoint: reqwest::Url,
+    retry: RetrySettings,
+    client: reqwest::Client,
+    /// 单次请求的总超时，None 表示不限制
+    timeout: Option<Duration>,
+    /// 传输中没有数据进出的最长时间，None 表示不检测
+    idle_timeout: Option<Duration>,
+    /// 上传 / 下载限速（字节每秒），0 表示不限速
+    upload_limit: u64,
+    download_limit: u64,
+}
+
+impl TinyPngBackend {
+    pub fn new(
+        keys: Vec<ApiKey>,
+        endpoint: &str,
+        retry: RetrySettings,
+        proxy: &ProxySettings,
+        network: &NetworkSettings,
+    ) -> Result<Self> {
+        // 补上末尾斜杠，使 join 相对路径时保留基础路径（如 http://host/tinify/）
+        let base = format!("{}/", endpoint.trim().trim_end_matches('/'));
+        let endpoint = reqwest::Url::parse(&base)
+            .map_err(|e| CompressError::InvalidSettings(format!("API 地址无效: {}", e)))?;
+        Ok(Self {
+            keys,
+            endpoint,
+            retry,
+            client: client(proxy, network)?,
+            timeout: (network.timeout_secs > 0).then(|| Duration::from_secs(network.timeout_secs)),
+            idle_timeout: (network.idle_timeout_secs > 0)
+                .then(|| Duration::from_secs(network.idle_timeout_secs)),
+            upload_limit: network.upload_limit_kbps.saturating_mul(1024),
+            download_limit: network.download_limit_kbps.saturating_mul(1024),
+        })
+    }
+
+    /// 解析 output.url：相对路径基于 API 地址拼接，绝对地址原样使用
+    fn resolve(&self, url: &str) -> Result<reqwest::Url> {
+        self.endpoint
+            .join(url)
+            .map_err(|e| anyhow!("无效的输出地址 {}: {}", url, e))
+    }
+
+    /// 设置请求总超时。限速时传输耗时取决于文件大小和同时进行的任务数，
+    /// 按总超时会把正常的慢速传输当作失败反复重传，因此不设总超时（连接超时仍然有效，可随时取消）
+    fn timeout(&self, request: reqwest::RequestBuilder, limit: u64) -> reqwest::RequestBuilder {
+        match self.timeout {
+            Some(timeout) if limit == 0 => request.timeout(timeout),
+            _ => request,
+        }
+    }
+
+    /// 在空闲超时内等待 future 完成，超时返回 None
+    async fn within_idle<T>(&self, future: impl Future<Output = T>) -> Option<T> {
+        match self.idle_timeout {
+            Some(idle) => tokio::time::timeout(idle, future).await.ok(),
+            None => Some(future.await),
+        }
+    }
+
+    /// 连接卡住：超过空闲超时没有数据进出
+    fn stalled(&self, context: &str) -> Failure {
+        let secs = self.idle_timeout.unwrap_or_default().as_secs();
+        Failure::Transient(
+            CompressError::Network(format!("{}: 连接 {} 秒没有数据传输", context, secs)).into(),
+        )
+    }
+
+    /// 只向与 API 地址同源的请求附带 Key，避免把 Key 发给其它主机
+    fn authorize(
+        &self,
+        request: reqwest::RequestBuilder,
+        url: &reqwest::Url,
+        key: &ApiKey,
+    ) -> reqwest::RequestBuilder {
+        if url.origin() == self.endpoint.origin() {
+            request.basic_auth("api", Some(&key.key))
+        } else {
+            request
+        }
+    }
+}
+
+#[async_trait]
+impl CompressionBackend for TinyPngBackend {
+    fn name(&self) -> &'static str {
+        "tinypng"
+    }
+
+    async fn compress(
+        &self,
+        input: &Path,
+        output: &Path,
+        options: &CompressOptions,
+        progress: &ProgressFn,
+    ) -> Result<BackendOutput> {
+        // 重试时会重新上传 / 下载，总进度保持不回退
+        let progress = &monotonic(progress.clone());
+
+        // ── 上传阶段 (0-40%)：额度用完时换下一个 Key 重新上传 ──
+        let mut last_limit = None;
+        let mut uploaded = None;
+        for key in self.rotation().await {
+            let shrink = retry::run(
+                &self.retry,
+                &options.cancel,
+                |attempt, delay| progress(0, retrying(attempt, delay)),
+                || self.shrink(&key, input, progress),
+            )
+            .await?;
+            match shrink {
+                Shrink::Done {
+                    response,
+                    compression_count,
+                } => {
+                    set_exhausted(&key.key, false).await;
+                    uploaded = Some((key, response, compression_count));
+                    break;
+                }
+                Shrink::LimitReached(message) => {
+                    set_exhausted(&key.key, true).await;
+                    last_limit = Some(message);
+                }
+            }
+        }
+        let Some((key, tinify_resp, compression_count)) = uploaded else {
+            bail!(CompressError::QuotaExceeded(format!(
+                "所有 API Key 本月额度均已用完: {}",
+                last_limit.unwrap_or_default()
+            )));
+        };
+
+        // ── 处理阶段 (40-50%)：等待 TinyPNG 服务端压缩 ─────────
+        progress(40, Phase::Processing);
+
+        // ── 下载阶段 (50-99%)：流式下载，实时更新百分比 ─────────
+        // 格式转换 / 缩放：向 output.url POST 操作指令，响应体即处理后的图片
+        let target = options.convert_to.filter(|f| *f != options.format);
+        let format = target.unwrap_or(options.format);
+        let preserved: Vec<MetadataField> = options
+            .preserve
+            .iter()
+            .copied()
+            .filter(|f| preserve_supported(*f, format))
+            .collect();
+        let operations = output_operations(options, target, &preserved);
+        let output_url = self.resolve(&tinify_resp.output.url)?;
+        let downloaded = retry::run(
+            &self.retry,
+            &options.cancel,
+            |attempt, delay| progress(50, retrying(attempt, delay)),
+            || {
+                self.download(
+                    &key,
+                    &output_url,
+                    &operations,
+                    &tinify_resp.output,
+                    output,
+                    progress,
+                )
+            },
+        )
+        .await?;
+
+        Ok(BackendOutput {
+            size: downloaded.size,
+            format,
+            width: downloaded.width,
+            height: downloaded.height,
+            preserved,
+            compression_count,
+            api_key: Some(key),
+        })
+    }
+}
+
+impl TinyPngBackend {
+    /// 轮换顺序：本月未用完的 Key 在前；已用完的仍放在最后尝试，
+    /// 以防升级套餐等情况下本地记录已过时。读取 usage.json，放到阻塞线程池执行
+    async fn rotation(&self) -> Vec<ApiKey> {
+        let keys = self.keys.clone();
+        tokio::task::spawn_blocking(move || {
+            let (fresh, exhausted): (Vec<ApiKey>, Vec<ApiKey>) = keys
+                .into_iter()
+                .partition(|k| !usage::get(&k.key).exhausted);
+            fresh.into_iter().chain(exhausted).collect()
+        })
+        .await
+        .unwrap_or_else(|_| self.keys.clone())
+    }
+
+    /// 用指定 Key 上传原图到 /shrink
+    async fn shrink(
+        &self,
+        key: &ApiKey,
+        input: &Path,
+        progress: &ProgressFn,
+    ) -> std::result::Result<Shrink, Failure> {
+        progress(0, Phase::Uploading);
+
+        // 每次尝试都重新打开源文件，从头上传
+        let file = tokio::fs::File::open(input)
+            .await
+            .map_err(|e| CompressError::FileRead(format!("读取文件失败: {}", e)))?;
+        let input_size = file
+            .metadata()
+            .await
+            .map_err(|e| CompressError::FileRead(format!("读取文件失败: {}", e)))?
+            .len();
+
+        let shrink_url = self.resolve("shrink")?;
+        let request = self.timeout(self.client.post(shrink_url.clone()), self.upload_limit);
+        let activity = Arc::new(Activity::default());
+        let send = self
+            .authorize(request, &shrink_url, key)
+            .header("Content-Type", "application/octet-stream")
+            // 流式 body 默认分块传输，显式声明长度
+            .header("Content-Length", input_size)
+            .body(upload_body(
+                file,
+                input_size,
+                self.upload_limit,
+                progress.clone(),
+                activity.clone(),
+            ))
+            .send();
+        // 上传完成后等待服务端压缩、返回响应的时间同样计入空闲
+        let upload_resp = tokio::select! {
+            resp = send => resp.map_err(|e| request_failure("TinyPNG 上传失败", e))?,
+            _ = activity.stalled(self.idle_timeout) => return Err(self.stalled("TinyPNG 上传失败")),
+        };
+
+        // 本月累计压缩次数（失败响应也会携带），记录失败不影响本次压缩
+        let compression_count = header_u32(&upload_resp, "Compression-Count");
+        if let Some(count) = compression_count {
+            record_usage(&key.key, count).await;
+        }
+
+        let status = upload_resp.status();
+        if !status.is_success() {
+            let err: TinyPngError = upload_resp.json().await.unwrap_or(TinyPngError {
+                error: String::new(),
+                message: format!("HTTP 错误: {}", status),
+            });
+            if status == reqwest::StatusCode::TOO_MANY_REQUESTS || err.error == "TooManyRequests" {
+                return Ok(Shrink::LimitReached(err.message));
+            }
+            let message = format!("TinyPNG 上传失败: {}", err.message);
+            return Err(status_failure(&self.retry, status, message));
+        }
+
+        Ok(Shrink::Done {
+            response: upload_resp
+                .json()
+                .await
+                .map_err(|e| request_failure("读取 TinyPNG 响应失败", e))?,
+            compression_count,
+        })
+    }
+
+    /// 下载 output.url 并直接写入 dest；有操作指令时 POST，否则 GET
+    async fn download(
+        &self,
+        key: &ApiKey,
+        output_url: &reqwest::Url,
+        operations: &Option<serde_json::Value>,
+        output: &TinyPngOutput,
+        dest: &Path,
+        progress: &ProgressFn,
+    ) -> std::result::Result<Downloaded, Failure> {
+        progress(50, Phase::Downloading);
+        let request = match operations {
+            Some(body) => self.client.post(output_url.clone()).json(body),
+            None => self.client.get(output_url.clone()),
+        };
+        let request = self.timeout(request, self.download_limit);
+        let mut download_resp = self
+            .within_idle(self.authorize(request, output_url, key).send())
+            .await
+            .ok_or_else(|| self.stalled("下载压缩文件失败"))?
+            .map_err(|e| request_failure("下载压缩文件失败", e))?;
+
+        let status = download_resp.status();
+        if !status.is_success() {
+            let message = format!("下载压缩文件失败: HTTP {}", status);
+            return Err(status_failure(&self.retry, status, message));
+        }
+
+        // 处理后的大小与 output.size 不同，以响应头为准
+        let total_bytes = match operations {
+            Some(_) => download_resp.content_length().unwrap_or(0),
+            None => output.size,
+        };
+        // 缩放后原尺寸不再适用，只认响应头
+        let resized = operations
+            .as_ref()
+            .is_some_and(|ops| ops.get("resize").is_some());
+        let fallback = |size: Option<u32>| if resized { None } else { size };
+        let width = header_u32(&download_resp, "Image-Width").or(fallback(output.width));
+        let height = header_u32(&download_resp, "Image-Height").or(fallback(output.height));
+        // 重试时截断重写，不会残留上次的半截数据
+        let mut file = tokio::fs::File::create(dest)
+            .await
+            .map_err(|e| CompressError::DiskWrite(format!("创建临时文件失败: {}", e)))?;
+        let mut downloaded = 0u64;
+        let mut last_pct = 50u8;
+
+        // 读取中断（连接重置、超时）属于临时错误
+        // 限速等待在两次读取之间，不计入空闲
+        while let Some(chunk) = self
+            .within_idle(download_resp.chunk())
+            .await
+            .ok_or_else(|| self.stalled("下载读取失败"))?
+            .map_err(|e| request_failure("下载读取失败", e))?
+        {
+            file.write_all(&chunk)
+                .await
+                .map_err(|e| CompressError::DiskWrite(format!("写入临时文件失败: {}", e)))?;
+            // 读得慢了，TCP 窗口随之收紧，服务端发送速度也就降下来
+            throttle::DOWNLOAD
+                .pace(chunk.len(), self.download_limit)
+                .await;
+            downloaded += chunk.len() as u64;
+            if total_bytes > 0 {
+                // 下载占总进度的 50-99%，留 1% 给写文件
+                let pct = (50.0 + downloaded as f64 / total_bytes as f64 * 49.0) as u8;
+                if pct > last_pct {
+                    last_pct = pct;
+                    progress(pct, Phase::Downloading);
+                }
+            }
+        }
+
+        file.flush()
+            .await
+            .map_err(|e| CompressError::DiskWrite(format!("写入临时文件失败: {}", e)))?;
+
+        if downloaded < 64 {
+            return Err(Failure::Transient(
+                CompressError::CorruptDownload(format!(
+                    "下载的压缩文件异常（{}字节），请重试",
+                    downloaded
+                ))
+                .into(),
+            ));
+        }
+        // 与服务端报告的大小不符，说明传输中途被截断
+        if total_bytes > 0 && downloaded != total_bytes {
+            return Err(Failure::Transient(
+                CompressError::CorruptDownload(format!(
+                    "下载的压缩文件不完整（{}/{} 字节），请重试",
+                    downloaded, total_bytes
+                ))
+                .into(),
+            ));
+        }
+
+        Ok(Downloaded {
+            size: downloaded,
+            width,
+            height,
+        })
+    }
+}
+
+// ── Key 校验 ───────────────────────────────────────────────────
+// 不带图片请求 /shrink：已认证时服务端返回 400（缺少输入），
+// 不计入压缩次数，响应头同样带有本月用量
+
+impl TinyPngBackend {
+    pub async fn validate(&self, key: &ApiKey) -> KeyStatus {
+        let status = |state, compression_count, message: String| KeyStatus {
+            state,
+            compression_count,
+            message,
+        };
+        let shrink_url = match self.resolve("shrink") {
+            Ok(url) => url,
+            Err(e) => return status(KeyState::NetworkError, None, e.to_string()),
+        };
+        let request = self.timeout(self.client.post(shrink_url.clone()), 0);
+        let resp = match self.authorize(request, &shrink_url, key).send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                let message = format!("无法连接 TinyPNG: {}", e);
+                return status(KeyState::NetworkError, None, message);
+            }
+        };
+
+        let compression_count = header_u32(&resp, "Compression-Count");
+        if let Some(count) = compression_count {
+            record_usage(&key.key, count).await;
+        }
+        let code = resp.status();
+        let err: TinyPngError = resp.json().await.unwrap_or(TinyPngError {
+            error: String::new(),
+            message: format!("HTTP 错误: {}", code),
+        });
+        match code {
+            reqwest::StatusCode::BAD_REQUEST => {
+                set_exhausted(&key.key, false).await;
+                status(KeyState::Valid, compression_count, "API Key 有效".into())
+            }
+            reqwest::StatusCode::UNAUTHORIZED => {
+                status(KeyState::Invalid, compression_count, err.message)
+            }
+            _ if code == reqwest::StatusCode::TOO_MANY_REQUESTS
+                || err.error == "TooManyRequests" =>
+            {
+                set_exhausted(&key.key, true).await;
+                let message = format!("API Key 有效，但本月额度已用完: {}", err.message);
+                status(KeyState::LimitReached, compression_count, message)
+            }
+            _ => {
+                let message = format!("TinyPNG 服务异常: {}", err.message);
+                status(KeyState::NetworkError, compression_count, message)
+            }
+        }
+    }
+}
+
+/// 组装对 output.url 的操作；没有任何操作时返回 None，直接 GET 下载
+fn output_operations(
+    options: &CompressOptions,
+    target: Option<ImageFormat>,
+    preserved: &[MetadataField],
+) -> Option<serde_json::Value> {
+    let mut ops = serde_json::Map::new();
+    if let Some(format) = target {
+        ops.insert(
+            "convert".into(),
+            serde_json::json!({ "type": format.mime() }),
+        );
+        // JPEG 不支持透明，需指定背景色，否则透明区域会变黑
+        if format == ImageFormat::Jpeg {
+            ops.insert(
+                "transform".into(),
+                serde_json::json!({ "background": options.background }),
+            );
+        }
+    }
+    if let Some(resize) = &options.resize {
+        let mut spec = serde_json::json!({ "method": resize.method });
+        if let Some(w) = resize.width {
+            spec["width"] = w.into();
+        }
+        if let Some(h) = resize.height {
+            spec["height"] = h.into();
+        }
+        ops.insert("resize".into(), spec);
+    }
+    if !preserved.is_empty() {
+        ops.insert("preserve".into(), serde_json::json!(preserved));
+    }
+    (!ops.is_empty()).then_some(serde_json::Value::Object(ops))
+}
+
+/// TinyPNG 只支持保留 JPEG 的全部元数据和 PNG 的版权信息
+fn preserve_supported(field: MetadataField, format: ImageFormat) -> bool {
+    match format {
+        ImageFormat::Jpeg => true,
+        ImageFormat::Png => field == MetadataField::Copyright,
+        _ => false,
+    }
+}
+
+/// 请求发送 / 读取失败：超时、连接失败、传输中断可重试
+fn request_failure(context: &str, e: reqwest::Error) -> Failure {
+    let err = CompressError::Network(format!("{}: {}", context, e)).into();
+    if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() || e.is_decode() {
+        Failure::Transient(err)
+    } else {
+        Failure::Fatal(err)
+    }
+}
+
+/// 非 2xx 响应：按状态码标明错误类别，状态码在重试列表中时可重试
+fn status_failure(policy: &RetrySettings, status: reqwest::StatusCode, message: String) -> Failure {
+    let err = match status.as_u16() {
+        401 => CompressError::Unauthorized(message),
+        413 => CompressError::FileTooLarge(message),
+        415 => CompressError::UnsupportedFormat(message),
+        code if code >= 500 => CompressError::Server(message),
+        _ => CompressError::Other(message),
+    }
+    .into();
+    if policy.retry_statuses.contains(&status.as_u16()) {
+        Failure::Transient(err)
+    } else {
+        Failure::Fatal(err)
+    }
+}
+
+fn header_u32(resp: &reqwest::Response, name: &str) -> Option<u32> {
+    resp.headers().get(name)?.to_str().ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(endpoint: &str) -> TinyPngBackend {
+        TinyPngBackend::new(
+            vec![],
+            endpoint,
+            RetrySettings::default(),
+            &ProxySettings::default(),
+            &NetworkSettings::default(),
+        )
+        .unwrap()
+    }
+
+    fn authorized(backend: &TinyPngBackend, url: &reqwest::Url) -> bool {
+        let key = ApiKey {
+            key: "secret".to_string(),
+            ..Default::default()
+        };
+        backend
+            .authorize(backend.client.get(url.clone()), url, &key)
+            .build()
+            .unwrap()
+            .headers()
+            .contains_key(reqwest::header::AUTHORIZATION)
+    }
+
+    #[tokio::test]
+    async fn activity_stalls_only_when_idle() {
+        let idle = Some(Duration::from_millis(50));
+        let activity = Arc::new(Activity::default());
+
+        // 持续有数据进出时不判定卡住
+        let busy = tokio::spawn({
+            let activity = activity.clone();
+            async move {
+                for _ in 0..6 {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    activity.touch();
+                }
+            }
+        });
+        let stalled = tokio::time::timeout(Duration::from_millis(110), activity.stalled(idle));
+        assert!(stalled.await.is_err());
+        busy.await.unwrap();
+
+        // 按限速等待期间不判定卡住
+        activity.pacing.store(true, Ordering::Relaxed);
+        let stalled = tokio::time::timeout(Duration::from_millis(150), activity.stalled(idle));
+        assert!(stalled.await.is_err());
+
+        activity.pacing.store(false, Ordering::Relaxed);
+        let stalled = tokio::time::timeout(Duration::from_millis(500), activity.stalled(idle));
+        assert!(stalled.await.is_ok());
+        let never = tokio::time::timeout(Duration::from_millis(100), activity.stalled(None));
+        assert!(never.await.is_err());
+    }
+
+    #[test]
+    fn resolve_relative_output_url() {
+        let tinify = backend("https://api.tinify.com");
+        let url = tinify.resolve("/output/abc").unwrap();
+        assert_eq!(url.as_str(), "https://api.tinify.com/output/abc");
+        assert!(authorized(&tinify, &url));
+
+        // 带基础路径的 API 地址：相对路径保留基础路径，以 / 开头的路径从根开始
+        let mock = backend("http://localhost:8080/tinify/");
+        let url = mock.resolve("output/abc").unwrap();
+        assert_eq!(url.as_str(), "http://localhost:8080/tinify/output/abc");
+        assert!(authorized(&mock, &url));
+        let url = mock.resolve("/output/abc").unwrap();
+        assert_eq!(url.as_str(), "http://localhost:8080/output/abc");
+        assert!(authorized(&mock, &url));
+    }
+
+    #[test]
+    fn resolve_absolute_output_url() {
+        let tinify = backend("https://api.tinify.com");
+        let url = tinify.resolve("https://api.tinify.com/output/abc").unwrap();
+        assert_eq!(url.as_str(), "https://api.tinify.com/output/abc");
+        assert!(authorized(&tinify, &url));
+    }
+
+    #[test]
+    fn cross_origin_output_url_without_key() {
+        let tinify = backend("https://api.tinify.com");
+        for other in [
+            "https://cdn.tinify.com/output/abc",
+            "http://api.tinify.com/output/abc",
+            "https://api.tinify.com:8443/output/abc",
+        ] {
+            let url = tinify.resolve(other).unwrap();
+            assert_eq!(url.as_str(), other);
+            assert!(!authorized(&tinify, &url), "{} 不应附带 Key", other);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::backend::BackendOutput;
+use crate::format::ImageFormat;
+use crate::settings::{AppSettings, BackendKind, MetadataField};
+
+// ── 压缩结果缓存 ───────────────────────────────────────────────
+// 以输入内容的 SHA-256 + 压缩参数为键，记录输出内容的 SHA-256 和输出位置。
+// 输入本身就是之前的压缩结果、且不转换格式不缩放时直接跳过；同样的图片（如另一目录下的副本）
+// 按相同参数再次压缩时，从之前的输出复制一份，不再上传、不消耗额度
+
+/// 最多保留的记录数，超出时淘汰最早的
+const MAX_ENTRIES: usize = 10_000;
+
+// 首次使用时从磁盘加载，之后读写都在内存中进行，修改后整体写回
+static CACHE: Mutex<Option<HashMap<String, CacheEntry>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CacheEntry {
+    pub output_hash: String,
+    /// 输出文件位置，复用前需确认内容仍与 output_hash 一致
+    pub output_path: String,
+    pub output_size: u64,
+    pub format: ImageFormat,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    #[serde(default)]
+    pub preserved: Vec<MetadataField>,
+    /// 记录时间（Unix 秒），用于淘汰
+    pub updated: u64,
+}
+
+impl CacheEntry {
+    pub fn new(output_hash: String, output_path: &Path, output: &BackendOutput) -> Self {
+        Self {
+            output_hash,
+            output_path: output_path.to_string_lossy().into_owned(),
+            output_size: output.size,
+            format: output.format,
+            width: output.width,
+            height: output.height,
+            preserved: output.preserved.clone(),
+            updated: now(),
+        }
+    }
+
+    /// 转为后端输出，复用时与实际压缩走同样的校验和写入流程
+    pub fn to_output(&self) -> BackendOutput {
+        BackendOutput {
+            size: self.output_size,
+            format: self.format,
+            width: self.width,
+            height: self.height,
+            preserved: self.preserved.clone(),
+            compression_count: None,
+            api_key: None,
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn cache_path() -> PathBuf {
+    crate::settings::config_dir().join("cache.json")
+}
+
+// 测试不改动用户的缓存
+#[cfg(test)]
+fn cache_path() -> PathBuf {
+    std::env::temp_dir().join(format!("tinyimage-cache-{}.json", std::process::id()))
+}
+
+fn with_cache<T>(f: impl FnOnce(&mut HashMap<String, CacheEntry>) -> T) -> T {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let entries = cache.get_or_insert_with(|| {
+        fs::read_to_string(cache_path())
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    });
+    f(entries)
+}
+
+fn save(entries: &HashMap<String, CacheEntry>) -> Result<()> {
+    let path = cache_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string(entries)?)?;
+    Ok(())
+}
+
+/// 文件内容的 SHA-256（十六进制）；流式计算，大文件不会整块读入内存
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hex(&hasher.finalize()))
+}
+
+/// 影响压缩结果的参数的指纹，参数不同的结果不能互相复用
+pub fn options_id(settings: &AppSettings) -> String {
+    let backend = match settings.backend {
+        BackendKind::TinyPng => serde_json::json!({ "endpoint": settings.api_endpoint }),
+        BackendKind::Local => serde_json::json!({
+            "png": settings.local_png,
+            "jpeg": settings.local_jpeg,
+            "webp": settings.local_webp,
+            "avif": settings.local_avif,
+        }),
+    };
+    let params = serde_json::json!({
+        "backend": settings.backend,
+        "params": backend,
+        "convertTo": settings.convert_to,
+        "background": settings.convert_background,
+        "resize": settings.resize,
+        "preserve": settings.preserve,
+        "marked": settings.mark_output,
+    });
+    hex(&Sha256::digest(params.to_string().as_bytes())[..8])
+}
+
+fn key(input_hash: &str, options_id: &str) -> String {
+    format!("{}:{}", input_hash, options_id)
+}
+
+/// 该内容是否为之前某次压缩的输出
+pub fn is_output(hash: &str) -> bool {
+    with_cache(|entries| entries.values().any(|e| e.output_hash == hash))
+}
+
+/// 查找相同内容、相同参数的压缩记录
+pub fn lookup(input_hash: &str, options_id: &str) -> Option<CacheEntry> {
+    with_cache(|entries| entries.get(&key(input_hash, options_id)).cloned())
+}
+
+/// 记录一次压缩结果，超出上限时淘汰最早的记录
+pub fn record(input_hash: &str, options_id: &str, entry: CacheEntry) -> Result<()> {
+    with_cache(|entries| {
+        entries.insert(key(input_hash, options_id), entry);
+        if entries.len() > MAX_ENTRIES {
+            let mut by_age: Vec<(u64, String)> = entries
+                .iter()
+                .map(|(k, e)| (e.updated, k.clone()))
+                .collect();
+            by_age.sort_unstable();
+            for (_, k) in by_age.into_iter().take(entries.len() - MAX_ENTRIES) {
+                entries.remove(&k);
+            }
+        }
+        save(entries)
+    })
+}
+
+/// 清空缓存，返回清除的记录数
+pub fn clear() -> Result<usize> {
+    with_cache(|entries| {
+        let count = entries.len();
+        entries.clear();
+        save(entries)?;
+        Ok(count)
+    })
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(output_hash: &str, updated: u64) -> CacheEntry {
+        CacheEntry {
+            output_hash: output_hash.to_string(),
+            output_path: format!("/out/{}.png", output_hash),
+            output_size: 100,
+            format: ImageFormat::Png,
+            width: None,
+            height: None,
+            preserved: Vec::new(),
+            updated,
+        }
+    }
+
+    #[test]
+    fn lookup_hit_and_miss() {
+        record("lookup-in", "opts", entry("lookup-out", now())).unwrap();
+        let hit = lookup("lookup-in", "opts").unwrap();
+        assert_eq!(hit.output_hash, "lookup-out");
+        assert!(lookup("lookup-in", "other-opts").is_none());
+        assert!(lookup("lookup-missing", "opts").is_none());
+        assert!(is_output("lookup-out"));
+        assert!(!is_output("lookup-in"));
+    }
+
+    #[test]
+    fn options_id_separates_settings() {
+        let settings = AppSettings::default();
+        assert_eq!(options_id(&settings), options_id(&settings.clone()));
+        let changed = [
+            AppSettings {
+                backend: BackendKind::Local,
+                ..settings.clone()
+            },
+            AppSettings {
+                convert_to: Some(ImageFormat::Webp),
+                ..settings.clone()
+            },
+            AppSettings {
+                mark_output: !settings.mark_output,
+                ..settings.clone()
+            },
+            AppSettings {
+                api_endpoint: "http://localhost:8080".to_string(),
+                ..settings.clone()
+            },
+        ];
+        for other in &changed {
+            assert_ne!(options_id(&settings), options_id(other));
+        }
+
+        // 本地参数只影响本地后端的结果
+        let mut quality = settings.clone();
+        quality.local_jpeg.quality += 1;
+        assert_eq!(options_id(&settings), options_id(&quality));
+        let local = &changed[0];
+        let mut local_quality = local.clone();
+        local_quality.local_jpeg.quality += 1;
+        assert_ne!(options_id(local), options_id(&local_quality));
+    }
+
+    #[test]
+    fn record_evicts_oldest() {
+        with_cache(|entries| {
+            for i in 0..MAX_ENTRIES as u64 {
+                entries.insert(key(&format!("evict-{}", i), "opts"), entry("evict", i + 1));
+            }
+        });
+        record("evict-new", "opts", entry("evict-new-out", now())).unwrap();
+        with_cache(|entries| assert!(entries.len() <= MAX_ENTRIES));
+        assert!(lookup("evict-0", "opts").is_none());
+        assert!(lookup("evict-new", "opts").is_some());
+        assert!(lookup(&format!("evict-{}", MAX_ENTRIES - 1), "opts").is_some());
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::error::CompressError;
+
+// ── 取消压缩 ───────────────────────────────────────────────────
+// 每个压缩任务按文件路径登记一个取消标记；网络请求和重试等待
+// 与取消标记竞争，被取消后立即停止。排队中的任务在开始时检查。
+// 同一文件可能同时有多个任务（如窗口内压缩的同时又从右键菜单压缩），按路径保存列表
+
+static JOBS: Mutex<Option<HashMap<String, Vec<CancelToken>>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<Inner>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// 已取消时返回 Cancelled 错误，便于在各阶段之间用 ? 提前退出
+    pub fn check(&self) -> Result<(), CompressError> {
+        if self.is_cancelled() {
+            Err(CompressError::cancelled())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// 等待直到被取消；与网络请求 select，取消时丢弃请求 future 即可中止传输
+    pub async fn cancelled(&self) {
+        let notified = self.0.notify.notified();
+        tokio::pin!(notified);
+        // 先登记等待再检查标记，避免检查之后、等待之前的取消被错过
+        notified.as_mut().enable();
+        if self.is_cancelled() {
+            return;
+        }
+        notified.await;
+    }
+}
+
+/// 登记中的任务，离开作用域时自动注销
+pub struct Job {
+    path: String,
+    token: CancelToken,
+}
+
+impl Job {
+    pub fn token(&self) -> &CancelToken {
+        &self.token
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(map) = jobs.as_mut() else {
+            return;
+        };
+        // 同一路径的其他任务仍在登记中，只注销自己的
+        if let Some(tokens) = map.get_mut(&self.path) {
+            tokens.retain(|t| !Arc::ptr_eq(&t.0, &self.token.0));
+            if tokens.is_empty() {
+                map.remove(&self.path);
+            }
+        }
+    }
+}
+
+/// 为文件登记新的压缩任务
+pub fn register(path: &str) -> Job {
+    let token = CancelToken::default();
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    jobs.get_or_insert_with(HashMap::new)
+        .entry(path.to_string())
+        .or_default()
+        .push(token.clone());
+    Job {
+        path: path.to_string(),
+        token,
+    }
+}
+
+/// 取消指定文件的所有压缩任务，返回是否找到任务
+pub fn cancel(path: &str) -> bool {
+    let jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    match jobs.as_ref().and_then(|map| map.get(path)) {
+        Some(tokens) => {
+            tokens.iter().for_each(CancelToken::cancel);
+            true
+        }
+        None => false,
+    }
+}
+
+/// 取消所有进行中和排队中的任务，返回取消的数量
+pub fn cancel_all() -> usize {
+    let jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    jobs.as_ref().map_or(0, |map| {
+        map.values().flatten().for_each(CancelToken::cancel);
+        map.values().map(Vec::len).sum()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn cancelled_wakes_waiters() {
+        let token = CancelToken::default();
+        let waiter = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+        tokio::task::yield_now().await;
+        token.cancel();
+        tokio::time::timeout(std::time::Duration::from_secs(1), waiter)
+            .await
+            .expect("取消后应立即返回")
+            .unwrap();
+        // 已取消时直接返回
+        token.cancelled().await;
+    }
+
+    #[test]
+    fn same_path_keeps_every_job() {
+        let path = "/tmp/cancel-same-path.png";
+        let first = register(path);
+        let second = register(path);
+        assert!(cancel(path));
+        assert!(first.token().is_cancelled());
+        assert!(second.token().is_cancelled());
+
+        // 先结束的任务只注销自己，后登记的任务仍可被取消
+        drop(first);
+        let third = register(path);
+        drop(second);
+        assert!(cancel(path));
+        assert!(third.token().is_cancelled());
+        drop(third);
+        assert!(!cancel(path));
+    }
+}
@@ -1,232 +1,528 @@
 use anyhow::{anyhow, bail, Result};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::sync::OnceLock;
+use std::sync::Arc;
 use tauri::{AppHandle, Emitter};
+use tokio::io::AsyncReadExt;
+
+use crate::backend::{
+    self, BackendOutput, CompressOptions, CompressionBackend, Phase, ProgressFn,
+};
+use crate::cache::{self, CacheEntry};
+use crate::cancel::CancelToken;
+use crate::error::CompressError;
+use crate::format::{ImageFormat, SNIFF_LEN};
+use crate::marker::{self, Provenance};
+use crate::settings::{AppSettings, MetadataField, OutputMode};
+use crate::verify;
 
-use crate::settings::{AppSettings, OutputMode};
-
-// 全局共享 Client：避免每次压缩都重建 TLS 上下文和连接池
-static HTTP_CLIENT: OnceLock<reqwest::blocking::Client> = OnceLock::new();
+// ── 数据结构 ───────────────────────────────────────────────────
 
-fn client() -> &'static reqwest::blocking::Client {
-    HTTP_CLIENT.get_or_init(|| {
-        reqwest::blocking::Client::builder()
-            .connect_timeout(std::time::Duration::from_secs(30))
-            .timeout(std::time::Duration::from_secs(120))
-            .pool_max_idle_per_host(4)
-            .build()
-            .expect("构建 HTTP Client 失败")
-    })
+/// 压缩结果的处理方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressStatus {
+    /// 已写入输出文件
+    Compressed,
+    /// 节省不足或本身已是压缩结果，未写入任何文件，原图保持不变
+    Skipped,
 }
 
-// ── 数据结构 ───────────────────────────────────────────────────
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CompressResult {
+    pub status: CompressStatus,
+    /// 跳过的原因，仅 Skipped 时有值
+    pub skip_reason: Option<String>,
     pub input_size: u64,
+    /// 压缩结果的大小；跳过时为未写入的结果大小
     pub output_size: u64,
+    /// 输出文件路径；跳过时为原图路径
     pub output_path: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct TinyPngOutput {
-    url: String,
-    size: u64,
-}
-
-#[derive(Debug, Deserialize)]
-struct TinyPngResponse {
-    output: TinyPngOutput,
-    #[allow(dead_code)]
-    input: TinyPngInput,
-}
-
-#[derive(Debug, Deserialize)]
-struct TinyPngInput {
-    #[allow(dead_code)]
-    size: u64,
-}
-
-#[derive(Debug, Deserialize)]
-struct TinyPngError {
-    message: String,
+    pub backend: String,
+    /// 按文件内容识别出的输入格式
+    pub input_format: ImageFormat,
+    /// 扩展名与实际格式不符或没有扩展名
+    pub extension_mismatch: bool,
+    /// 输出格式，转换时与输入不同
+    pub format: ImageFormat,
+    /// 输出图片尺寸
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// 保留下来的元数据
+    pub preserved: Vec<MetadataField>,
+    /// TinyPNG 本月已压缩次数（所用 Key 的计数）
+    pub compression_count: Option<u32>,
+    /// 压缩所用 API Key 的备注名
+    pub api_key_label: Option<String>,
+    /// 结果来自缓存（复用之前的输出或识别为已压缩），未重新压缩、未消耗额度
+    pub from_cache: bool,
+    /// 压缩所用的 API Key，仅供后端统计额度，不返回前端
+    #[serde(skip)]
+    pub api_key: Option<String>,
 }
 
 // ── 进度事件 ───────────────────────────────────────────────────
 
 #[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
 struct ProgressEvent<'a> {
     path: &'a str,
     percent: u8,
-    phase: &'a str,
+    phase: &'static str,
+    /// 仅重试阶段：第几次重试、重试前等待的毫秒数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attempt: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delay_ms: Option<u64>,
 }
 
-fn emit_progress(app: &AppHandle, path: &str, percent: u8, phase: &str) {
-    app.emit("compress-progress", &ProgressEvent { path, percent, phase }).ok();
+fn emit_progress(app: &AppHandle, path: &str, percent: u8, phase: Phase) {
+    let (attempt, delay_ms) = match phase {
+        Phase::Retrying { attempt, delay_ms } => (Some(attempt), Some(delay_ms)),
+        _ => (None, None),
+    };
+    let event = ProgressEvent {
+        path,
+        percent,
+        phase: phase.name(),
+        attempt,
+        delay_ms,
+    };
+    app.emit("compress-progress", &event).ok();
 }
 
-// ── 上传进度 Reader ────────────────────────────────────────────
-// 包装内存数据，在 reqwest 读取 body 时实时发送上传百分比
-
-struct UploadProgress {
-    cursor: std::io::Cursor<Vec<u8>>,
-    total: u64,
-    app: AppHandle,
-    path: String,
-    last_pct: u8,
-}
+// ── 压缩入口 ───────────────────────────────────────────────────
 
-impl Read for UploadProgress {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let n = self.cursor.read(buf)?;
-        if self.total > 0 {
-            let pos = self.cursor.position();
-            // 上传占总进度的 0-40%
-            let pct = (pos as f64 / self.total as f64 * 40.0) as u8;
-            if pct > self.last_pct {
-                self.last_pct = pct;
-                emit_progress(&self.app, &self.path, pct, "uploading");
-            }
-        }
-        Ok(n)
-    }
+pub async fn compress_image(
+    file_path: &str,
+    settings: &AppSettings,
+    cancel: &CancelToken,
+    app: &AppHandle,
+) -> Result<CompressResult> {
+    let backend = backend::from_settings(settings)?;
+    compress_with(backend.as_ref(), file_path, settings, cancel, app).await
 }
 
-// ── 压缩入口 ───────────────────────────────────────────────────
-
-pub fn compress_image(
+/// 使用指定后端压缩单个文件：后端从原图流式写入临时文件 → 原子替换
+pub async fn compress_with(
+    backend: &dyn CompressionBackend,
     file_path: &str,
     settings: &AppSettings,
+    cancel: &CancelToken,
     app: &AppHandle,
 ) -> Result<CompressResult> {
-    if settings.api_key.is_empty() {
-        bail!("API Key 未配置，请在设置中填写 TinyPNG API Key");
+    // 排队期间可能已被取消
+    cancel.check()?;
+    let path = Path::new(file_path);
+    let Input {
+        format,
+        extension_mismatch,
+        size: input_size,
+        ..
+    } = inspect_input(path).await?;
+    validate_resize(settings)?;
+
+    // 已压缩过的文件原样返回，不写入任何文件
+    let skipped = |reason: String, from_cache: bool| CompressResult {
+        status: CompressStatus::Skipped,
+        skip_reason: Some(reason),
+        input_size,
+        output_size: input_size,
+        output_path: file_path.to_string(),
+        backend: backend.name().to_string(),
+        input_format: format,
+        extension_mismatch,
+        format,
+        width: None,
+        height: None,
+        preserved: Vec::new(),
+        compression_count: None,
+        api_key_label: None,
+        from_cache,
+        api_key: None,
+    };
+
+    // 带有 TinyImage 标记的文件已压缩过（可能是其他机器上压缩的），再按原格式、原尺寸压缩没有意义，
+    // 直接跳过；要求转换格式或缩放时照常处理
+    if settings.mark_output && recompress_only(format, settings) {
+        if let Some(provenance) = read_marker(path).await? {
+            return Ok(skipped(marker_reason(&provenance), false));
+        }
     }
 
-    let path = Path::new(file_path);
-    if !path.exists() {
-        bail!("文件不存在: {}", file_path);
+    // 输入本身就是之前的压缩结果时同样只在再压缩一遍时跳过，避免重复上传
+    let cache_key = if settings.cache_enabled {
+        Some((hash_file(path).await?, cache::options_id(settings)))
+    } else {
+        None
+    };
+    if let Some((input_hash, _)) = &cache_key {
+        if recompress_only(format, settings) && cache::is_output(input_hash) {
+            return Ok(skipped("已是 TinyImage 压缩过的图片".to_string(), true));
+        }
     }
 
-    let input_data = fs::read(path)?;
-    let input_size = input_data.len() as u64;
+    let progress: ProgressFn = {
+        let app = app.clone();
+        let file_path = file_path.to_string();
+        Arc::new(move |pct, phase| emit_progress(&app, &file_path, pct, phase))
+    };
+    let options = compress_options(format, settings, cancel);
+    let output_path = resolve_output_path(path, options.output_format(), settings)?;
+    check_output_target(path, &output_path, options.output_format()).await?;
+    if let Some(parent) = output_path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| CompressError::DiskWrite(format!("创建输出目录失败: {}", e)))?;
+    }
 
-    // ── 上传阶段 (0-40%) ────────────────────────────────────────
-    emit_progress(app, file_path, 0, "uploading");
+    // 后端先写到目标旁的临时文件再原子替换，避免 overwrite 模式下失败时损坏原图
+    let tmp = TempFile::new(output_path.with_extension("__tinytmp__"));
+    // 相同内容按相同参数压缩过、且之前的输出仍在时复制过来，不再调用后端
+    let cached = match &cache_key {
+        Some((input_hash, options_id)) => cache::lookup(input_hash, options_id),
+        None => None,
+    };
+    let (mut output, from_cache) = match cached {
+        Some(entry) if copy_cached(&entry, &tmp.path).await => (entry.to_output(), true),
+        _ => (
+            backend
+                .compress(path, &tmp.path, &options, &progress)
+                .await?,
+            false,
+        ),
+    };
+    // 新的压缩结果写入标记；复用的输出在当初压缩时已写入
+    if settings.mark_output && !from_cache {
+        let provenance = Provenance {
+            backend: backend.name().to_string(),
+            original_size: input_size,
+        };
+        if let Some(size) = embed_marker(&tmp.path, output.format, provenance).await? {
+            output.size = size;
+        }
+    }
 
-    let body = reqwest::blocking::Body::sized(
-        UploadProgress {
-            total: input_size,
-            cursor: std::io::Cursor::new(input_data),
-            app: app.clone(),
-            path: file_path.to_string(),
-            last_pct: 0,
-        },
-        input_size,
-    );
-
-    let upload_resp = client()
-        .post("https://api.tinify.com/shrink")
-        .basic_auth("api", Some(&settings.api_key))
-        .header("Content-Type", "application/octet-stream")
-        .body(body)
-        .send()?;
-
-    let status = upload_resp.status();
-    if !status.is_success() {
-        let err: TinyPngError = upload_resp
-            .json()
-            .unwrap_or(TinyPngError { message: format!("HTTP 错误: {}", status) });
-        bail!("TinyPNG 上传失败: {}", err.message);
-    }
-
-    // ── 处理阶段 (40-50%)：等待 TinyPNG 服务端压缩 ─────────────
-    emit_progress(app, file_path, 40, "processing");
-    let tinify_resp: TinyPngResponse = upload_resp.json()?;
-
-    // ── 下载阶段 (50-99%)：流式下载，实时更新百分比 ─────────────
-    emit_progress(app, file_path, 50, "downloading");
-
-    let mut download_resp = client()
-        .get(&tinify_resp.output.url)
-        .basic_auth("api", Some(&settings.api_key))
-        .send()?;
-
-    if !download_resp.status().is_success() {
-        bail!("下载压缩文件失败: HTTP {}", download_resp.status());
-    }
-
-    let total_bytes = tinify_resp.output.size;
-    let mut compressed_data = Vec::with_capacity(total_bytes as usize);
-    let mut downloaded = 0u64;
-    let mut last_pct = 50u8;
-    let mut buf = [0u8; 16_384];
-
-    loop {
-        let n = download_resp
-            .read(&mut buf)
-            .map_err(|e| anyhow!("下载读取失败: {}", e))?;
-        if n == 0 {
-            break;
+    // 节省不足时丢弃结果（临时文件随 tmp 删除），不产生无用的输出文件；
+    // 转换格式是明确要求的新文件，不受此限制
+    let skip_reason = if output.format == format {
+        settings.min_savings.shortfall(input_size, output.size)
+    } else {
+        None
+    };
+    let (status, output_path) = match skip_reason {
+        Some(_) => (CompressStatus::Skipped, path.to_path_buf()),
+        None => {
+            // 校验通过才替换，原图不会被损坏的结果覆盖
+            verify_output(path, &tmp.path, &output, settings.resize.is_some()).await?;
+            let output_hash = match (&cache_key, from_cache) {
+                (Some(_), false) => Some(hash_file(&tmp.path).await?),
+                _ => None,
+            };
+            // 替换前最后一次检查，取消后不再改动任何文件
+            cancel.check()?;
+            tmp.persist(&output_path).await?;
+            if let (Some((input_hash, options_id)), Some(output_hash)) = (&cache_key, output_hash) {
+                let entry = CacheEntry::new(output_hash, &output_path, &output);
+                record_cache(input_hash, options_id, entry).await;
+            }
+            (CompressStatus::Compressed, output_path)
         }
-        compressed_data.extend_from_slice(&buf[..n]);
-        downloaded += n as u64;
-        if total_bytes > 0 {
-            // 下载占总进度的 50-99%，留 1% 给写文件
-            let pct = (50.0 + downloaded as f64 / total_bytes as f64 * 49.0) as u8;
-            if pct > last_pct {
-                last_pct = pct;
-                emit_progress(app, file_path, pct, "downloading");
+    };
+
+    Ok(CompressResult {
+        status,
+        skip_reason,
+        input_size,
+        output_size: output.size,
+        output_path: output_path.to_string_lossy().into_owned(),
+        backend: backend.name().to_string(),
+        input_format: format,
+        extension_mismatch,
+        format: output.format,
+        width: output.width,
+        height: output.height,
+        preserved: output.preserved,
+        compression_count: output.compression_count,
+        api_key_label: output.api_key.as_ref().map(|k| k.display_name()),
+        from_cache,
+        api_key: output.api_key.map(|k| k.key),
+    })
+}
+
+/// 计算文件内容哈希；大文件读取耗时，放到阻塞线程池执行
+pub async fn hash_file(path: &Path) -> Result<String> {
+    let path = path.to_path_buf();
+    let hash = tokio::task::spawn_blocking(move || cache::hash_file(&path))
+        .await
+        .map_err(|e| anyhow!("哈希计算任务异常退出: {}", e))?
+        .map_err(|e| CompressError::FileRead(format!("读取文件失败: {}", e)))?;
+    Ok(hash)
+}
+
+/// 记录压缩结果；缓存索引会整体写回磁盘，放到阻塞线程池执行。写入失败不影响本次结果
+async fn record_cache(input_hash: &str, options_id: &str, entry: CacheEntry) {
+    let (input_hash, options_id) = (input_hash.to_string(), options_id.to_string());
+    tokio::task::spawn_blocking(move || cache::record(&input_hash, &options_id, entry))
+        .await
+        .ok();
+}
+
+/// 读取文件中的压缩标记；涉及文件读取，放到阻塞线程池执行
+pub async fn read_marker(path: &Path) -> Result<Option<Provenance>> {
+    let path = path.to_path_buf();
+    let provenance = tokio::task::spawn_blocking(move || marker::detect(&path))
+        .await
+        .map_err(|e| anyhow!("读取标记任务异常退出: {}", e))?
+        .map_err(|e| CompressError::FileRead(format!("读取文件失败: {}", e)))?;
+    Ok(provenance)
+}
+
+/// 检查输出位置，返回原图以外的位置是否已有文件、压缩后会被覆盖。
+/// 扩展名有误或转换格式时输出会换成新扩展名，该位置的文件可能与本次压缩无关
+/// （如 overwrite 模式下 logo.png 实为 JPEG、输出到已存在的 logo.jpg），
+/// 只有它是之前的压缩结果（带 TinyImage 标记或在缓存中）才允许替换，否则报错
+pub async fn check_output_target(
+    input: &Path,
+    output: &Path,
+    output_format: ImageFormat,
+) -> Result<bool> {
+    if output == input || !tokio::fs::try_exists(output).await.unwrap_or(false) {
+        return Ok(false);
+    }
+    if ImageFormat::from_path(input) == Some(output_format) {
+        return Ok(true);
+    }
+    let previous = read_marker(output).await.ok().flatten().is_some()
+        || matches!(hash_file(output).await, Ok(hash) if cache::is_output(&hash));
+    if !previous {
+        bail!(CompressError::DiskWrite(format!(
+            "输出位置已有文件 {}，为避免覆盖已停止，请移走该文件或更换输出方式",
+            output.display()
+        )));
+    }
+    Ok(true)
+}
+
+/// 在临时文件中写入压缩标记，返回写入后的大小；格式不支持（AVIF）时不改动，返回 None。
+/// 流式复制到旁边的文件再替换回来，大图不会整块读入内存
+async fn embed_marker(
+    path: &Path,
+    format: ImageFormat,
+    provenance: Provenance,
+) -> Result<Option<u64>> {
+    let path = path.to_path_buf();
+    let size = tokio::task::spawn_blocking(move || -> std::io::Result<Option<u64>> {
+        let marked = path.with_extension("__tinymark__");
+        let size = marker::embed_file(&path, &marked, format, &provenance).and_then(|size| {
+            if size.is_some() {
+                fs::rename(&marked, &path)?;
             }
+            Ok(size)
+        });
+        if size.is_err() {
+            let _ = fs::remove_file(&marked);
         }
+        size
+    })
+    .await
+    .map_err(|e| anyhow!("写入标记任务异常退出: {}", e))?
+    .map_err(|e| CompressError::DiskWrite(format!("写入压缩标记失败: {}", e)))?;
+    Ok(size)
+}
+
+/// 把缓存记录的输出复制到临时文件；原输出已被删除或改动时返回 false，改为重新压缩
+async fn copy_cached(entry: &CacheEntry, tmp: &Path) -> bool {
+    if tokio::fs::copy(&entry.output_path, tmp).await.is_err() {
+        return false;
     }
+    matches!(hash_file(tmp).await, Ok(hash) if hash == entry.output_hash)
+}
 
-    if compressed_data.len() < 64 {
-        bail!(
-            "下载的压缩文件异常（{}字节），请重试",
-            compressed_data.len()
-        );
+/// 压缩前从原图读取的信息
+pub struct Input {
+    /// 按文件头识别出的真实格式
+    pub format: ImageFormat,
+    /// 扩展名与实际格式不符或没有扩展名
+    pub extension_mismatch: bool,
+    pub size: u64,
+    /// 文件头，供后端检查能否处理
+    pub header: Vec<u8>,
+}
+
+/// 检查原图是否存在，按文件头判断真实格式（扩展名可能有误或缺失）并读取大小
+pub async fn inspect_input(path: &Path) -> Result<Input> {
+    if !path.exists() {
+        bail!(CompressError::FileRead(format!(
+            "文件不存在: {}",
+            path.display()
+        )));
     }
+    let mut header = Vec::new();
+    tokio::fs::File::open(path)
+        .await
+        .map_err(|e| CompressError::FileRead(format!("读取文件失败: {}", e)))?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut header)
+        .await
+        .map_err(|e| CompressError::FileRead(format!("读取文件失败: {}", e)))?;
+    let format = ImageFormat::sniff(&header).ok_or_else(|| {
+        CompressError::UnsupportedFormat(format!("不是支持的图片格式: {}", path.display()))
+    })?;
+    let size = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| CompressError::FileRead(format!("读取文件失败: {}", e)))?
+        .len();
+    Ok(Input {
+        format,
+        extension_mismatch: ImageFormat::from_path(path) != Some(format),
+        size,
+        header,
+    })
+}
 
-    let output_size = compressed_data.len() as u64;
-    let output_path = resolve_output_path(path, settings)?;
+/// 按设置生成传给后端的压缩参数
+pub fn compress_options(
+    format: ImageFormat,
+    settings: &AppSettings,
+    cancel: &CancelToken,
+) -> CompressOptions {
+    CompressOptions {
+        format,
+        convert_to: settings.convert_to,
+        background: settings.convert_background.clone(),
+        resize: settings.resize.clone(),
+        preserve: settings.preserve.fields(),
+        cancel: cancel.clone(),
+    }
+}
 
-    if let Some(parent) = output_path.parent() {
-        fs::create_dir_all(parent)?;
+/// 缩放参数不合法时返回 InvalidSettings
+pub fn validate_resize(settings: &AppSettings) -> Result<()> {
+    if let Some(resize) = &settings.resize {
+        resize
+            .validate()
+            .map_err(|e| CompressError::InvalidSettings(e.to_string()))?;
     }
+    Ok(())
+}
 
-    // 先写临时文件再原子替换，避免 overwrite 模式下失败时损坏原图
-    let tmp_path = output_path.with_extension("__tinytmp__");
-    fs::write(&tmp_path, &compressed_data)
-        .map_err(|e| anyhow!("写入临时文件失败: {}", e))?;
-    fs::rename(&tmp_path, &output_path).map_err(|e| {
-        let _ = fs::remove_file(&tmp_path);
-        anyhow!("移动文件失败: {}", e)
-    })?;
+/// 按当前设置处理只会再压缩一遍：不转换格式（或转换为原格式）且不缩放。
+/// 已压缩过的图片只在这种情况下跳过
+pub fn recompress_only(format: ImageFormat, settings: &AppSettings) -> bool {
+    settings.convert_to.is_none_or(|to| to == format) && settings.resize.is_none()
+}
 
-    Ok(CompressResult {
-        input_size,
-        output_size,
-        output_path: output_path.to_string_lossy().into_owned(),
+/// 带标记文件的跳过原因，如「已由 TinyImage 压缩过（TinyPNG，原始 120.0 KB）」
+pub fn marker_reason(provenance: &Provenance) -> String {
+    let backend = match provenance.backend.as_str() {
+        "tinypng" => "TinyPNG",
+        "local" => "本地压缩",
+        other => other,
+    };
+    format!(
+        "已由 TinyImage 压缩过（{}，原始 {:.1} KB）",
+        backend,
+        provenance.original_size as f64 / 1024.0
+    )
+}
+
+/// 校验临时文件；需要完整解码图片，放到阻塞线程池执行
+async fn verify_output(
+    input: &Path,
+    tmp: &Path,
+    output: &BackendOutput,
+    resized: bool,
+) -> Result<()> {
+    let (input, tmp) = (input.to_path_buf(), tmp.to_path_buf());
+    let (format, size) = (output.format, output.size);
+    let reported = output.width.zip(output.height);
+    tokio::task::spawn_blocking(move || {
+        let (dimensions, allow_rotated) = match reported {
+            Some(dimensions) => (Some(dimensions), false),
+            // 后端未报告尺寸时，未缩放的结果应与原图一致（可能按 EXIF 方向旋转过）
+            None if !resized => (verify::dimensions(&input), true),
+            None => (None, false),
+        };
+        verify::verify(
+            &tmp,
+            &verify::Expected {
+                format,
+                size,
+                dimensions,
+                allow_rotated,
+            },
+        )
     })
+    .await
+    .map_err(|e| anyhow!("校验任务异常退出: {}", e))??;
+    Ok(())
+}
+
+/// 临时文件：未 persist 就离开作用域（出错、取消、panic）时自动删除
+struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            persisted: false,
+        }
+    }
+
+    /// 原子替换到目标路径
+    async fn persist(mut self, target: &Path) -> Result<()> {
+        tokio::fs::rename(&self.path, target)
+            .await
+            .map_err(|e| CompressError::DiskWrite(format!("移动文件失败: {}", e)))?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
 }
 
-fn resolve_output_path(input: &Path, settings: &AppSettings) -> Result<PathBuf> {
+/// 计算输出路径；输出格式与原文件扩展名不符（格式转换，或扩展名本身有误）时替换扩展名。
+/// 此时 Overwrite 模式无法原地替换，改为在原图旁写入新扩展名的文件。
+pub fn resolve_output_path(
+    input: &Path,
+    output_format: ImageFormat,
+    settings: &AppSettings,
+) -> Result<PathBuf> {
+    let converted = ImageFormat::from_path(input) != Some(output_format);
+    let stem = input
+        .file_stem()
+        .ok_or_else(|| anyhow!("无法获取文件名"))?
+        .to_string_lossy();
+    let ext = if converted {
+        output_format.extension().to_string()
+    } else {
+        input
+            .extension()
+            .map(|e| e.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
     match settings.output_mode {
-        OutputMode::Overwrite => Ok(input.to_path_buf()),
+        OutputMode::Overwrite => {
+            if converted {
+                Ok(input.with_extension(ext))
+            } else {
+                Ok(input.to_path_buf())
+            }
+        }
 
         OutputMode::Alongside => {
-            let stem = input
-                .file_stem()
-                .ok_or_else(|| anyhow!("无法获取文件名"))?
-                .to_string_lossy();
-            let ext = input
-                .extension()
-                .map(|e| e.to_string_lossy().into_owned())
-                .unwrap_or_default();
             let new_name = if ext.is_empty() {
                 format!("{}-tiny", stem)
             } else {
@@ -238,13 +534,119 @@ fn resolve_output_path(input: &Path, settings: &AppSettings) -> Result<PathBuf>
 
         OutputMode::Directory => {
             if settings.output_directory.is_empty() {
-                bail!("请先在设置中指定输出目录");
+                bail!(CompressError::InvalidSettings(
+                    "请先在设置中指定输出目录".to_string()
+                ));
             }
             let dir = Path::new(&settings.output_directory);
-            let filename = input
-                .file_name()
-                .ok_or_else(|| anyhow!("无法获取文件名"))?;
-            Ok(dir.join(filename))
+            if converted {
+                Ok(dir.join(format!("{}.{}", stem, ext)))
+            } else {
+                let filename = input
+                    .file_name()
+                    .ok_or_else(|| anyhow!("无法获取文件名"))?;
+                Ok(dir.join(filename))
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::{ResizeMethod, ResizeSpec};
+
+    #[test]
+    fn recompress_only_without_convert_or_resize() {
+        let mut settings = AppSettings::default();
+        assert!(recompress_only(ImageFormat::Png, &settings));
+        settings.convert_to = Some(ImageFormat::Png);
+        assert!(recompress_only(ImageFormat::Png, &settings));
+        settings.convert_to = Some(ImageFormat::Webp);
+        assert!(!recompress_only(ImageFormat::Png, &settings));
+
+        settings.convert_to = None;
+        settings.resize = Some(ResizeSpec {
+            method: ResizeMethod::Fit,
+            width: Some(100),
+            height: Some(100),
+        });
+        assert!(!recompress_only(ImageFormat::Png, &settings));
+    }
+
+    fn output(input: &str, format: ImageFormat, mode: OutputMode) -> PathBuf {
+        let settings = AppSettings {
+            output_mode: mode,
+            output_directory: "/out".to_string(),
+            ..AppSettings::default()
+        };
+        resolve_output_path(Path::new(input), format, &settings).unwrap()
+    }
+
+    #[test]
+    fn output_path_same_format() {
+        let png = ImageFormat::Png;
+        assert_eq!(
+            output("/img/a.png", png, OutputMode::Overwrite),
+            Path::new("/img/a.png")
+        );
+        assert_eq!(
+            output("/img/a.png", png, OutputMode::Alongside),
+            Path::new("/img/a-tiny.png")
+        );
+        assert_eq!(
+            output("/img/a.png", png, OutputMode::Directory),
+            Path::new("/out/a.png")
+        );
+    }
+
+    #[test]
+    fn output_path_converted() {
+        let webp = ImageFormat::Webp;
+        assert_eq!(
+            output("/img/a.png", webp, OutputMode::Overwrite),
+            Path::new("/img/a.webp")
+        );
+        assert_eq!(
+            output("/img/a.png", webp, OutputMode::Alongside),
+            Path::new("/img/a-tiny.webp")
+        );
+        assert_eq!(
+            output("/img/a.png", webp, OutputMode::Directory),
+            Path::new("/out/a.webp")
+        );
+    }
+
+    #[test]
+    fn output_path_extension_mismatch() {
+        // logo.png 实为 JPEG：输出换成与内容一致的扩展名
+        let jpeg = ImageFormat::Jpeg;
+        assert_eq!(
+            output("/img/logo.png", jpeg, OutputMode::Overwrite),
+            Path::new("/img/logo.jpg")
+        );
+        assert_eq!(
+            output("/img/logo.png", jpeg, OutputMode::Alongside),
+            Path::new("/img/logo-tiny.jpg")
+        );
+        assert_eq!(
+            output("/img/logo.png", jpeg, OutputMode::Directory),
+            Path::new("/out/logo.jpg")
+        );
+        // 没有扩展名时同样补上
+        assert_eq!(
+            output("/img/logo", jpeg, OutputMode::Alongside),
+            Path::new("/img/logo-tiny.jpg")
+        );
+    }
+
+    #[test]
+    fn output_directory_required() {
+        let settings = AppSettings {
+            output_mode: OutputMode::Directory,
+            ..AppSettings::default()
+        };
+        let result = resolve_output_path(Path::new("/img/a.png"), ImageFormat::Png, &settings);
+        assert!(result.is_err());
+    }
+}
@@ -47,7 +47,7 @@ pub fn register() -> Result<()> {
     let exe_path = exe.to_string_lossy().into_owned();
     let hkcu = RegKey::predef(HKEY_CURRENT_USER);
 
-    for ext in &["png", "jpg", "jpeg", "webp"] {
+    for ext in &["png", "jpg", "jpeg", "webp", "avif"] {
         let key_path = format!(
             r"Software\Classes\SystemFileAssociations\.{}\shell\TinyImage",
             ext
@@ -81,7 +81,7 @@ pub fn unregister() -> Result<()> {
     use std::os::windows::process::CommandExt;
 
     let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    for ext in &["png", "jpg", "jpeg", "webp"] {
+    for ext in &["png", "jpg", "jpeg", "webp", "avif"] {
         let key_path = format!(
             r"Software\Classes\SystemFileAssociations\.{}\shell\TinyImage",
             ext
@@ -0,0 +1,161 @@
+use serde::ser::{Serialize, SerializeStruct, Serializer};
+use std::fmt;
+
+// ── 压缩错误 ───────────────────────────────────────────────────
+// 内部仍用 anyhow 传递，在出错处用 CompressError 标明类别；
+// 命令返回前从错误链中取出，前端据此给出处理建议、判断是否值得重试
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompressError {
+    /// API Key 未配置或无效
+    Unauthorized(String),
+    /// 所有 API Key 本月额度均已用完
+    QuotaExceeded(String),
+    /// 不支持的图片格式，或图片已损坏无法解码
+    UnsupportedFormat(String),
+    /// 图片超过服务端大小限制
+    FileTooLarge(String),
+    /// 源文件不存在或无法读取
+    FileRead(String),
+    /// 网络连接失败、超时或传输中断
+    Network(String),
+    /// TinyPNG 服务端错误（5xx）
+    Server(String),
+    /// 写入输出文件失败
+    DiskWrite(String),
+    /// 下载的压缩文件不完整或已损坏
+    CorruptDownload(String),
+    /// 压缩结果校验未通过（格式、尺寸不符或无法解码），原图保持不变
+    VerifyFailed(String),
+    /// 设置有误，如缩放参数、输出目录、API 地址
+    InvalidSettings(String),
+    /// 用户取消
+    Cancelled(String),
+    Other(String),
+}
+
+impl CompressError {
+    pub fn cancelled() -> Self {
+        Self::Cancelled("已取消".to_string())
+    }
+
+    /// 序列化给前端的类别标识
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Unauthorized(_) => "unauthorized",
+            Self::QuotaExceeded(_) => "quotaExceeded",
+            Self::UnsupportedFormat(_) => "unsupportedFormat",
+            Self::FileTooLarge(_) => "fileTooLarge",
+            Self::FileRead(_) => "fileRead",
+            Self::Network(_) => "network",
+            Self::Server(_) => "server",
+            Self::DiskWrite(_) => "diskWrite",
+            Self::CorruptDownload(_) => "corruptDownload",
+            Self::VerifyFailed(_) => "verifyFailed",
+            Self::InvalidSettings(_) => "invalidSettings",
+            Self::Cancelled(_) => "cancelled",
+            Self::Other(_) => "other",
+        }
+    }
+
+    /// 汇总通知中使用的简短名称
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Unauthorized(_) => "API Key 无效",
+            Self::QuotaExceeded(_) => "额度用完",
+            Self::UnsupportedFormat(_) => "格式不支持",
+            Self::FileTooLarge(_) => "文件过大",
+            Self::FileRead(_) => "读取失败",
+            Self::Network(_) => "网络错误",
+            Self::Server(_) => "服务端错误",
+            Self::DiskWrite(_) => "写入失败",
+            Self::CorruptDownload(_) => "下载损坏",
+            Self::VerifyFailed(_) => "校验失败",
+            Self::InvalidSettings(_) => "设置有误",
+            Self::Cancelled(_) => "已取消",
+            Self::Other(_) => "其它错误",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Unauthorized(m)
+            | Self::QuotaExceeded(m)
+            | Self::UnsupportedFormat(m)
+            | Self::FileTooLarge(m)
+            | Self::FileRead(m)
+            | Self::Network(m)
+            | Self::Server(m)
+            | Self::DiskWrite(m)
+            | Self::CorruptDownload(m)
+            | Self::VerifyFailed(m)
+            | Self::InvalidSettings(m)
+            | Self::Cancelled(m)
+            | Self::Other(m) => m,
+        }
+    }
+
+    /// 不改设置、原样再试一次是否可能成功
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            Self::Network(_)
+                | Self::Server(_)
+                | Self::CorruptDownload(_)
+                | Self::VerifyFailed(_)
+                | Self::DiskWrite(_)
+        )
+    }
+
+    /// 保留类别，替换错误信息
+    pub fn with_message(&self, message: String) -> Self {
+        match self {
+            Self::Unauthorized(_) => Self::Unauthorized(message),
+            Self::QuotaExceeded(_) => Self::QuotaExceeded(message),
+            Self::UnsupportedFormat(_) => Self::UnsupportedFormat(message),
+            Self::FileTooLarge(_) => Self::FileTooLarge(message),
+            Self::FileRead(_) => Self::FileRead(message),
+            Self::Network(_) => Self::Network(message),
+            Self::Server(_) => Self::Server(message),
+            Self::DiskWrite(_) => Self::DiskWrite(message),
+            Self::CorruptDownload(_) => Self::CorruptDownload(message),
+            Self::VerifyFailed(_) => Self::VerifyFailed(message),
+            Self::InvalidSettings(_) => Self::InvalidSettings(message),
+            Self::Cancelled(_) => Self::Cancelled(message),
+            Self::Other(_) => Self::Other(message),
+        }
+    }
+}
+
+impl fmt::Display for CompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+impl std::error::Error for CompressError {}
+
+/// 从 anyhow 错误链中取出 CompressError；没有标明类别的按来源推断
+impl From<anyhow::Error> for CompressError {
+    fn from(e: anyhow::Error) -> Self {
+        let message = e.to_string();
+        if let Some(err) = e.chain().find_map(|c| c.downcast_ref::<CompressError>()) {
+            return err.with_message(message);
+        }
+        if e.chain().any(|c| c.is::<reqwest::Error>()) {
+            return Self::Network(message);
+        }
+        Self::Other(message)
+    }
+}
+
+/// 序列化为 { kind, message, retryable }
+impl Serialize for CompressError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("CompressError", 3)?;
+        s.serialize_field("kind", self.kind())?;
+        s.serialize_field("message", self.message())?;
+        s.serialize_field("retryable", &self.retryable())?;
+        s.end()
+    }
+}
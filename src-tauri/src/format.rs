@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::Path;
+
+/// 判断格式需要读取的文件头长度
+pub const SNIFF_LEN: u64 = 64;
+
+/// 支持压缩的图片格式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+}
+
+impl ImageFormat {
+    /// 根据文件扩展名判断格式（不区分大小写）
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_lowercase();
+        match ext.as_str() {
+            "png" => Some(Self::Png),
+            "jpg" | "jpeg" => Some(Self::Jpeg),
+            "webp" => Some(Self::Webp),
+            "avif" => Some(Self::Avif),
+            _ => None,
+        }
+    }
+
+    /// 根据文件头（magic bytes）判断真实格式，与扩展名无关
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+            return Some(Self::Png);
+        }
+        if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            return Some(Self::Jpeg);
+        }
+        if header.len() >= 12 && &header[..4] == b"RIFF" && &header[8..12] == b"WEBP" {
+            return Some(Self::Webp);
+        }
+        // ISOBMFF：ftyp box 的主品牌或兼容品牌中含 avif / avis
+        if header.len() >= 16 && &header[4..8] == b"ftyp" {
+            let box_len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+            let major = &header[8..12];
+            // 第 12-16 字节是版本号，之后才是兼容品牌列表
+            let compatible = &header[16..box_len.clamp(16, header.len())];
+            if std::iter::once(major)
+                .chain(compatible.chunks_exact(4))
+                .any(|brand| brand == b"avif" || brand == b"avis")
+            {
+                return Some(Self::Avif);
+            }
+        }
+        None
+    }
+
+    /// 读取文件头判断真实格式，不是支持的图片时返回 None
+    pub fn detect(path: &Path) -> std::io::Result<Option<Self>> {
+        let mut header = Vec::with_capacity(SNIFF_LEN as usize);
+        std::fs::File::open(path)?
+            .take(SNIFF_LEN)
+            .read_to_end(&mut header)?;
+        Ok(Self::sniff(&header))
+    }
+
+    /// 输出文件使用的扩展名
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_magic_bytes() {
+        assert_eq!(
+            ImageFormat::sniff(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR"),
+            Some(ImageFormat::Png)
+        );
+        assert_eq!(
+            ImageFormat::sniff(&[0xFF, 0xD8, 0xFF, 0xE0]),
+            Some(ImageFormat::Jpeg)
+        );
+        assert_eq!(
+            ImageFormat::sniff(b"RIFF\x10\0\0\0WEBPVP8 "),
+            Some(ImageFormat::Webp)
+        );
+        assert_eq!(ImageFormat::sniff(b"RIFF\x10\0\0\0WAVEfmt "), None);
+        assert_eq!(ImageFormat::sniff(b"GIF89a"), None);
+    }
+
+    #[test]
+    fn sniff_avif_brands() {
+        // 主品牌
+        assert_eq!(
+            ImageFormat::sniff(b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf"),
+            Some(ImageFormat::Avif)
+        );
+        // 兼容品牌
+        assert_eq!(
+            ImageFormat::sniff(b"\0\0\0\x1cftypmif1\0\0\0\0mif1avifmiaf"),
+            Some(ImageFormat::Avif)
+        );
+        // HEIC 不是 AVIF
+        assert_eq!(
+            ImageFormat::sniff(b"\0\0\0\x18ftypheic\0\0\0\0mif1heic"),
+            None
+        );
+        // ftyp box 之外的 avif 不算
+        assert_eq!(ImageFormat::sniff(b"\0\0\0\x10ftypheic\0\0\0\0avif"), None);
+    }
+
+    #[test]
+    fn sniff_truncated_header() {
+        assert_eq!(ImageFormat::sniff(b""), None);
+        assert_eq!(ImageFormat::sniff(b"\x89PNG"), None);
+        assert_eq!(ImageFormat::sniff(&[0xFF, 0xD8]), None);
+        assert_eq!(ImageFormat::sniff(b"RIFF\x10\0\0\0WEB"), None);
+        assert_eq!(ImageFormat::sniff(b"\0\0\0\x1cftypmif1"), None);
+        // box 长度大于实际数据、小于 16 时都不越界
+        assert_eq!(
+            ImageFormat::sniff(b"\xff\0\0\0ftypmif1\0\0\0\0avif"),
+            Some(ImageFormat::Avif)
+        );
+        assert_eq!(ImageFormat::sniff(b"\0\0\0\x04ftypmif1\0\0\0\0avif"), None);
+    }
+}
@@ -1,6 +1,15 @@
+mod backend;
+mod cache;
+mod cancel;
 mod compress;
 mod context_menu;
+mod error;
+mod format;
+mod marker;
+mod plan;
 mod settings;
+mod usage;
+mod verify;
 
 use std::sync::atomic::Ordering;
 use tauri::{AppHandle, Emitter, Manager};
@@ -22,58 +31,78 @@ static IS_BACKGROUND: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicB
 // ── 后台压缩计数器 ────────────────────────────────────────────
 // 用于多文件后台压缩：等所有任务结束后再通知并退出
 static BG_PENDING: std::sync::atomic::AtomicI32 = std::sync::atomic::AtomicI32::new(0);
-static BG_RESULTS: Mutex<(u32, u32)> = Mutex::new((0, 0)); // (success, error)
+static BG_RESULTS: Mutex<(u32, u32, u32)> = Mutex::new((0, 0, 0)); // (success, skipped, error)
+// 按错误类别统计失败数，保持首次出现的顺序
+static BG_ERRORS: Mutex<Vec<(&'static str, u32)>> = Mutex::new(Vec::new());
 
 // ── 后台压缩辅助函数 ──────────────────────────────────────────
-// 为每个文件启动一个 spawn_blocking 任务；全部完成后发通知，
+// 为每个文件启动一个异步任务；全部完成后发通知，
 // 若是纯后台模式（IS_BACKGROUND）则退出 app。
 fn spawn_bg_compress(app: AppHandle, files: Vec<String>) {
     let settings = settings::load();
 
-    // API Key 未配置时直接提示，不进入压缩流程
-    if settings.api_key.is_empty() {
-        let handle = app.clone();
-        tauri::async_runtime::spawn(async move {
-            handle
-                .notification()
-                .builder()
-                .title("TinyImage")
-                .body("请先在 TinyImage 设置中配置 TinyPNG API Key")
-                .show()
-                .ok();
-            if IS_BACKGROUND.load(Ordering::SeqCst) {
-                tokio::time::sleep(std::time::Duration::from_millis(300)).await;
-                handle.exit(0);
-            }
-        });
-        return;
-    }
+    // 后端不可用（如 API Key 未配置）时直接提示，不进入压缩流程
+    let backend = match backend::from_settings(&settings) {
+        Ok(b) => b,
+        Err(e) => {
+            let handle = app.clone();
+            let message = e.to_string();
+            tauri::async_runtime::spawn(async move {
+                handle
+                    .notification()
+                    .builder()
+                    .title("TinyImage")
+                    .body(&message)
+                    .show()
+                    .ok();
+                if IS_BACKGROUND.load(Ordering::SeqCst) {
+                    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+                    handle.exit(0);
+                }
+            });
+            return;
+        }
+    };
 
     for file in files {
         let prev = BG_PENDING.fetch_add(1, Ordering::SeqCst);
         // 新的一批次开始（从 0 变为 1），重置累计结果，避免跨批次数字累加
         if prev == 0 {
             if let Ok(mut g) = BG_RESULTS.lock() {
-                *g = (0, 0);
+                *g = (0, 0, 0);
+            }
+            if let Ok(mut g) = BG_ERRORS.lock() {
+                g.clear();
             }
         }
         let handle = app.clone();
         let f = file.clone();
         let s = settings.clone();
+        let b = backend.clone();
+        // 立即登记，尚未开始的任务也能被 cancel_all 取消
+        let job = cancel::register(&file);
         tauri::async_runtime::spawn(async move {
-            let handle2 = handle.clone();
-            let res = tokio::task::spawn_blocking(move || {
-                compress::compress_image(&f, &s, &handle2)
-            })
-            .await
-            .unwrap_or_else(|_| Err(anyhow::anyhow!("panic")));
+            let res = compress::compress_with(b.as_ref(), &f, &s, job.token(), &handle)
+                .await
+                .map_err(error::CompressError::from);
+            drop(job);
+            if let Ok(r) = &res {
+                warn_quota(&handle, &s, r);
+            }
 
             {
                 let mut g = BG_RESULTS.lock().unwrap_or_else(|e| e.into_inner());
-                if res.is_ok() {
-                    g.0 += 1;
-                } else {
-                    g.1 += 1;
+                match &res {
+                    Ok(r) if r.status == compress::CompressStatus::Skipped => g.1 += 1,
+                    Ok(_) => g.0 += 1,
+                    Err(e) => {
+                        g.2 += 1;
+                        let mut kinds = BG_ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+                        match kinds.iter_mut().find(|(label, _)| *label == e.label()) {
+                            Some((_, n)) => *n += 1,
+                            None => kinds.push((e.label(), 1)),
+                        }
+                    }
                 }
             }
 
@@ -85,12 +114,19 @@ fn spawn_bg_compress(app: AppHandle, files: Vec<String>) {
                     return; // 有新任务加入，不退出
                 }
 
-                let (ok, err) = *BG_RESULTS.lock().unwrap_or_else(|e| e.into_inner());
+                let (ok, skipped, err) = *BG_RESULTS.lock().unwrap_or_else(|e| e.into_inner());
                 // 后台模式强制使用系统通知（窗口隐藏，dialog 无法显示）
                 let message = if err == 0 {
-                    format!("成功压缩 {} 张图片", ok)
+                    result_summary(ok, skipped, err)
                 } else {
-                    format!("压缩完成：{} 成功，{} 失败", ok, err)
+                    // 附上失败原因分布，如「额度用完 2，网络错误 1」
+                    let kinds = BG_ERRORS.lock().unwrap_or_else(|e| e.into_inner());
+                    let detail = kinds
+                        .iter()
+                        .map(|(label, n)| format!("{} {}", label, n))
+                        .collect::<Vec<_>>()
+                        .join("，");
+                    format!("{}（{}）", result_summary(ok, skipped, err), detail)
                 };
                 handle
                     .notification()
@@ -109,6 +145,31 @@ fn spawn_bg_compress(app: AppHandle, files: Vec<String>) {
     }
 }
 
+/// 后台预估（--dry-run）：与窗口内的预估相同，只计算每个文件的处理方式和预计节省，
+/// 以系统通知汇报，不访问 TinyPNG、不写入任何文件。
+/// 完整的逐文件结果以 JSON 输出到标准输出，便于从命令行查看
+fn spawn_bg_plan(app: AppHandle, files: Vec<String>) {
+    let settings = settings::load();
+    tauri::async_runtime::spawn(async move {
+        let plans = plan::plan(&files, &settings).await;
+        if let Ok(json) = serde_json::to_string_pretty(&plans) {
+            println!("{}", json);
+        }
+        app.notification()
+            .builder()
+            .title("TinyImage")
+            .body(plan_summary(&plans))
+            .show()
+            .ok();
+
+        // 同时有后台压缩在进行时，由压缩任务结束后退出
+        if IS_BACKGROUND.load(Ordering::SeqCst) && BG_PENDING.load(Ordering::SeqCst) == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+            app.exit(0);
+        }
+    });
+}
+
 // ── 普通命令 ──────────────────────────────────────────────────
 
 #[tauri::command]
@@ -144,20 +205,20 @@ fn save_settings(settings: settings::AppSettings) -> Result<(), String> {
 fn get_image_preview(path: String) -> Result<String, String> {
     use base64::Engine;
     let data = std::fs::read(&path).map_err(|e| e.to_string())?;
-    let ext = std::path::Path::new(&path)
-        .extension()
-        .and_then(|e| e.to_str())
-        .unwrap_or("jpg")
-        .to_lowercase();
-    let mime = match ext.as_str() {
-        "png" => "image/png",
-        "webp" => "image/webp",
-        _ => "image/jpeg",
-    };
+    // 按内容判断 MIME，扩展名可能有误
+    let mime = format::ImageFormat::sniff(&data)
+        .unwrap_or(format::ImageFormat::Jpeg)
+        .mime();
     let b64 = base64::engine::general_purpose::STANDARD.encode(&data);
     Ok(format!("data:{};base64,{}", mime, b64))
 }
 
+/// 前端添加文件（拖拽、选择文件）前筛选出图片
+#[tauri::command]
+fn filter_images(paths: Vec<String>) -> Vec<String> {
+    filter_image_args(paths)
+}
+
 /// 将 tinyimage://compress?file=path1&file=path2 中的文件路径解析出来
 fn parse_files_from_url(url: &str) -> Vec<String> {
     let query = match url.find('?') {
@@ -207,14 +268,156 @@ fn percent_decode(s: &str) -> String {
 async fn compress_image(
     app: AppHandle,
     file_path: String,
+    mut settings: settings::AppSettings,
+    convert_to: Option<format::ImageFormat>,
+    resize: Option<settings::ResizeSpec>,
+) -> Result<compress::CompressResult, error::CompressError> {
+    // 单次调用指定的转换格式、缩放参数优先于设置
+    if convert_to.is_some() {
+        settings.convert_to = convert_to;
+    }
+    if resize.is_some() {
+        settings.resize = resize;
+    }
+    let job = cancel::register(&file_path);
+    let result = compress::compress_image(&file_path, &settings, job.token(), &app).await?;
+    warn_quota(&app, &settings, &result);
+    Ok(result)
+}
+
+/// 预估（dry run）：按与 compress_image 相同的设置计算每个文件的处理方式、
+/// 输出位置和预计大小，不访问 TinyPNG、不写入任何文件
+#[tauri::command]
+async fn plan_compression(
+    file_paths: Vec<String>,
+    mut settings: settings::AppSettings,
+    convert_to: Option<format::ImageFormat>,
+    resize: Option<settings::ResizeSpec>,
+) -> Vec<plan::FilePlan> {
+    if convert_to.is_some() {
+        settings.convert_to = convert_to;
+    }
+    if resize.is_some() {
+        settings.resize = resize;
+    }
+    plan::plan(&file_paths, &settings).await
+}
+
+/// 取消指定文件的压缩，返回是否找到进行中或排队中的任务
+#[tauri::command]
+fn cancel_compression(path: String) -> bool {
+    cancel::cancel(&path)
+}
+
+/// 取消所有压缩任务（含后台压缩），返回取消的数量
+#[tauri::command]
+fn cancel_all() -> usize {
+    cancel::cancel_all()
+}
+
+#[derive(serde::Serialize, Clone)]
+struct QuotaWarning {
+    label: String,
+    count: u32,
+    limit: u32,
+}
+
+/// 本月压缩次数接近限额时提醒：通知前端弹窗，通知模式与后台模式下弹出系统通知
+fn warn_quota(
+    app: &AppHandle,
+    settings: &settings::AppSettings,
+    result: &compress::CompressResult,
+) {
+    let (Some(count), Some(key)) = (result.compression_count, &result.api_key) else {
+        return;
+    };
+    let limit = settings.monthly_limit;
+    if !usage::should_warn(key, count, limit) {
+        return;
+    }
+    let label = result.api_key_label.clone().unwrap_or_default();
+    app.emit(
+        "quota-warning",
+        QuotaWarning {
+            label: label.clone(),
+            count,
+            limit,
+        },
+    )
+    .ok();
+    // 窗口打开时由前端弹窗提醒（静默模式也提醒）；通知模式与后台模式使用系统通知
+    let notify = settings.notify_mode == settings::NotifyMode::Notification;
+    if notify || IS_BACKGROUND.load(Ordering::SeqCst) {
+        app.notification()
+            .builder()
+            .title("TinyImage")
+            .body(format!(
+                "API Key「{}」本月已压缩 {} 张，接近 {} 张的限额",
+                label, count, limit
+            ))
+            .show()
+            .ok();
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CompressionUsage {
+    month: String,
+    count: u32,
+    limit: u32,
+    /// 本月额度已用完，轮换时会被排到最后
+    exhausted: bool,
+}
+
+/// 查询本月已用压缩次数；未传 Key 时使用已保存设置中的第一个 Key
+#[tauri::command]
+fn get_compression_count(api_key: Option<String>) -> CompressionUsage {
+    let settings = settings::load();
+    let key = api_key
+        .or_else(|| settings.active_keys().into_iter().next().map(|k| k.key))
+        .unwrap_or_default();
+    let usage = usage::get(&key);
+    CompressionUsage {
+        month: usage.month,
+        count: usage.count,
+        limit: settings.monthly_limit,
+        exhausted: usage.exhausted,
+    }
+}
+
+/// 校验 API Key 并刷新本月用量，不消耗压缩次数；
+/// 使用设置页当前填写（可能尚未保存）的 API 地址、代理和超时
+#[tauri::command]
+async fn validate_api_key(
+    api_key: String,
     settings: settings::AppSettings,
-) -> Result<compress::CompressResult, String> {
-    tokio::task::spawn_blocking(move || {
-        compress::compress_image(&file_path, &settings, &app)
-    })
-    .await
-    .map_err(|e| e.to_string())?
-    .map_err(|e| e.to_string())
+) -> Result<backend::KeyStatus, error::CompressError> {
+    let key = settings::ApiKey {
+        label: String::new(),
+        key: api_key.trim().to_string(),
+    };
+    if key.key.is_empty() {
+        return Ok(backend::KeyStatus {
+            state: backend::KeyState::Invalid,
+            compression_count: None,
+            message: "API Key 为空".to_string(),
+        });
+    }
+    let backend = backend::TinyPngBackend::new(
+        vec![key.clone()],
+        &settings.api_endpoint,
+        settings.retry.clone(),
+        &settings.proxy,
+        &settings.network,
+    )?;
+    Ok(backend.validate(&key).await)
+}
+
+/// 清空压缩结果缓存，返回清除的记录数
+#[tauri::command]
+fn clear_cache() -> Result<usize, String> {
+    cache::clear().map_err(|e| e.to_string())
 }
 
 // ── 通知命令 ──────────────────────────────────────────────────
@@ -224,23 +427,17 @@ fn notify_result(
     app: AppHandle,
     settings: settings::AppSettings,
     success_count: u32,
+    skipped_count: u32,
     error_count: u32,
 ) -> Result<(), String> {
     use settings::NotifyMode;
 
-    let total = success_count + error_count;
+    let total = success_count + skipped_count + error_count;
     if total == 0 {
         return Ok(());
     }
 
-    let message = if error_count == 0 {
-        format!("成功压缩 {} 张图片", success_count)
-    } else {
-        format!(
-            "压缩完成：{} 成功，{} 失败",
-            success_count, error_count
-        )
-    };
+    let message = result_summary(success_count, skipped_count, error_count);
 
     match settings.notify_mode {
         NotifyMode::Silent => {}
@@ -263,6 +460,62 @@ fn notify_result(
     Ok(())
 }
 
+/// 批量压缩结束时的通知文案；跳过的文件（节省不足、已压缩过）单独计数
+fn result_summary(success: u32, skipped: u32, error: u32) -> String {
+    match (skipped, error) {
+        (0, 0) => format!("成功压缩 {} 张图片", success),
+        (_, 0) if success == 0 => format!("{} 张图片已跳过，原图未改动", skipped),
+        (_, 0) => format!("压缩完成：{} 成功，{} 跳过", success, skipped),
+        (0, _) => format!("压缩完成：{} 成功，{} 失败", success, error),
+        _ => format!(
+            "压缩完成：{} 成功，{} 跳过，{} 失败",
+            success, skipped, error
+        ),
+    }
+}
+
+/// 预估结果汇总，如「预估：3 张会压缩，1 张跳过，预计节省 1.2 MB，消耗 3 次额度」
+fn plan_summary(plans: &[plan::FilePlan]) -> String {
+    use plan::PlanAction;
+
+    let count = |action: PlanAction| plans.iter().filter(|p| p.action == action).count();
+    let mut parts = vec![format!(
+        "{} 张会压缩",
+        count(PlanAction::Compress) + count(PlanAction::Reuse)
+    )];
+    for (action, label) in [(PlanAction::Skip, "跳过"), (PlanAction::Error, "无法压缩")] {
+        if count(action) > 0 {
+            parts.push(format!("{} 张{}", count(action), label));
+        }
+    }
+    let saved: u64 = plans
+        .iter()
+        .filter(|p| matches!(p.action, PlanAction::Compress | PlanAction::Reuse))
+        .map(|p| {
+            p.input_size
+                .saturating_sub(p.estimated_size.unwrap_or(p.input_size))
+        })
+        .sum();
+    parts.push(format!("预计节省 {}", format_size(saved)));
+    let quota = plans.iter().filter(|p| p.uses_quota).count();
+    if quota > 0 {
+        parts.push(format!("消耗 {} 次额度", quota));
+    }
+    let conflicts = plans.iter().filter(|p| !p.conflicts.is_empty()).count();
+    if conflicts > 0 {
+        parts.push(format!("{} 张输出位置有冲突", conflicts));
+    }
+    format!("预估：{}", parts.join("，"))
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.2} MB", bytes as f64 / 1024.0 / 1024.0),
+    }
+}
+
 // ── 右键菜单命令 ──────────────────────────────────────────────
 
 #[tauri::command]
@@ -277,23 +530,30 @@ fn unregister_context_menu() -> Result<(), String> {
 
 // ── 工具函数 ──────────────────────────────────────────────────
 
+/// 按文件内容筛选出图片，没有扩展名或扩展名有误的图片也能识别；
+/// 读不到的文件按扩展名判断，留给压缩流程报告具体错误
 fn filter_image_args(args: Vec<String>) -> Vec<String> {
-    let image_exts = ["png", "jpg", "jpeg", "webp"];
     args.into_iter()
         .filter(|a| {
-            let lower = a.to_lowercase();
-            image_exts.iter().any(|ext| lower.ends_with(ext))
+            let path = std::path::Path::new(a);
+            match format::ImageFormat::detect(path) {
+                Ok(detected) => detected.is_some(),
+                Err(_) => format::ImageFormat::from_path(path).is_some(),
+            }
         })
         .collect()
 }
 
-/// 解析命令行参数，返回 (is_compress_mode, file_paths)
-fn parse_args(raw: Vec<String>) -> (bool, Vec<String>) {
+/// 解析命令行参数，返回 (is_compress_mode, is_dry_run, file_paths)
+fn parse_args(raw: Vec<String>) -> (bool, bool, Vec<String>) {
     let is_compress = raw.iter().any(|a| a == "--compress");
+    let dry_run = raw.iter().any(|a| a == "--dry-run");
     let files = filter_image_args(
-        raw.into_iter().filter(|a| a != "--compress").collect(),
+        raw.into_iter()
+            .filter(|a| a != "--compress" && a != "--dry-run")
+            .collect(),
     );
-    (is_compress, files)
+    (is_compress, dry_run, files)
 }
 
 // ── Tauri 入口 ────────────────────────────────────────────────
@@ -304,7 +564,7 @@ pub fn run() {
         // 单实例插件必须第一个注册
         .plugin(tauri_plugin_single_instance::init(|app, argv, _cwd| {
             // argv 是第二个实例的完整 std::env::args()，skip(1) 去掉程序路径
-            let (is_compress, files) = parse_args(argv.into_iter().skip(1).collect());
+            let (is_compress, dry_run, files) = parse_args(argv.into_iter().skip(1).collect());
 
             if is_compress && !files.is_empty() {
                 // 右键"用TinyImage压缩"：在当前进程后台压缩，不影响 UI。
@@ -320,7 +580,11 @@ pub fn run() {
                     #[cfg(target_os = "macos")]
                     unsafe { setActivationPolicyAccessory() };
                 }
-                spawn_bg_compress(app.clone(), files);
+                if dry_run {
+                    spawn_bg_plan(app.clone(), files);
+                } else {
+                    spawn_bg_compress(app.clone(), files);
+                }
             } else if !files.is_empty() {
                 // 打开方式：仅添加文件到列表，不自动压缩
                 if FRONTEND_READY.load(Ordering::SeqCst) {
@@ -366,18 +630,20 @@ pub fn run() {
             // ── 解析启动参数 ──────────────────────────────────
             // skip(1) 去掉 argv[0]（程序路径），再 skip(1) 去掉 Tauri 内部注入的 URL scheme 参数
             let raw: Vec<String> = std::env::args().skip(1).collect();
-            let (is_compress, file_args) = parse_args(raw);
+            let (is_compress, dry_run, file_args) = parse_args(raw);
 
             if is_compress {
                 // 右键"用TinyImage压缩"首次启动：纯后台模式，压缩完退出
                 IS_BACKGROUND.store(true, Ordering::SeqCst);
                 #[cfg(target_os = "macos")]
                 unsafe { setActivationPolicyAccessory() };
-                if !file_args.is_empty() {
-                    spawn_bg_compress(app.handle().clone(), file_args);
-                } else {
+                if file_args.is_empty() {
                     // --compress 但没有文件，直接退出
                     app.handle().exit(0);
+                } else if dry_run {
+                    spawn_bg_plan(app.handle().clone(), file_args);
+                } else {
+                    spawn_bg_compress(app.handle().clone(), file_args);
                 }
             } else if !file_args.is_empty() {
                 // 通过"打开方式"启动：显示窗口，仅添加文件，不自动压缩
@@ -443,7 +709,11 @@ pub fn run() {
                             #[cfg(target_os = "macos")]
                             unsafe { setActivationPolicyAccessory() };
                         }
-                        spawn_bg_compress(handle.clone(), files);
+                        if u.contains("dryrun=1") {
+                            spawn_bg_plan(handle.clone(), files);
+                        } else {
+                            spawn_bg_compress(handle.clone(), files);
+                        }
                         continue;
                     }
 
@@ -471,7 +741,14 @@ pub fn run() {
             save_settings,
             get_image_preview,
             compress_image,
+            plan_compression,
+            cancel_compression,
+            cancel_all,
             notify_result,
+            get_compression_count,
+            validate_api_key,
+            clear_cache,
+            filter_images,
             register_context_menu,
             unregister_context_menu,
             get_startup_files,
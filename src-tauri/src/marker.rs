@@ -0,0 +1,737 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::format::ImageFormat;
+
+// ── 压缩标记 ───────────────────────────────────────────────────
+// 在压缩结果中写入一段标记（PNG tEXt、JPEG COM、WebP XMP），记录压缩工具、
+// 后端和原始大小。标记随文件经过 git、网盘传到其他机器，再次压缩时据此跳过，
+// 不依赖本机缓存。AVIF 暂不写入
+
+const TOOL: &str = "TinyImage";
+const XMP_NS: &str = "urn:com.tinyimage.desktop:provenance:1.0/";
+
+/// 标记记录的压缩来源
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provenance {
+    /// 压缩所用后端，如 tinypng / local
+    pub backend: String,
+    /// 压缩前的文件大小
+    pub original_size: u64,
+}
+
+impl Provenance {
+    /// PNG / JPEG 使用的文本形式，如 `tool=TinyImage/0.1.0; backend=tinypng; original-size=12345`
+    fn to_text(&self) -> String {
+        format!(
+            "tool={}/{}; backend={}; original-size={}",
+            TOOL,
+            env!("CARGO_PKG_VERSION"),
+            self.backend,
+            self.original_size
+        )
+    }
+
+    fn parse_text(text: &str) -> Option<Self> {
+        let (mut tool, mut backend, mut original_size) = (false, None, None);
+        for field in text.split(';') {
+            match field.trim().split_once('=') {
+                Some(("tool", value)) => tool = value.split('/').next() == Some(TOOL),
+                Some(("backend", value)) => backend = Some(value.to_string()),
+                Some(("original-size", value)) => original_size = value.parse().ok(),
+                _ => {}
+            }
+        }
+        if !tool {
+            return None;
+        }
+        Some(Self {
+            backend: backend?,
+            original_size: original_size?,
+        })
+    }
+
+    /// WebP 使用的 XMP 包，属性放在独立的命名空间下
+    fn to_xmp(&self) -> String {
+        format!(
+            concat!(
+                "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>",
+                "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">",
+                "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">",
+                "<rdf:Description rdf:about=\"\" xmlns:tinyimage=\"{ns}\"",
+                " tinyimage:tool=\"{tool}/{version}\" tinyimage:backend=\"{backend}\"",
+                " tinyimage:originalSize=\"{size}\"/>",
+                "</rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>"
+            ),
+            ns = XMP_NS,
+            tool = TOOL,
+            version = env!("CARGO_PKG_VERSION"),
+            backend = self.backend,
+            size = self.original_size,
+        )
+    }
+
+    fn parse_xmp(xmp: &str) -> Option<Self> {
+        if !xmp.contains(XMP_NS) {
+            return None;
+        }
+        let attr = |name: &str| {
+            let prefix = format!("tinyimage:{}=\"", name);
+            let start = xmp.find(&prefix)? + prefix.len();
+            let end = start + xmp[start..].find('"')?;
+            Some(&xmp[start..end])
+        };
+        Some(Self {
+            backend: attr("backend")?.to_string(),
+            original_size: attr("originalSize")?.parse().ok()?,
+        })
+    }
+}
+
+/// 把 src 复制到 dst 并写入标记，返回 dst 的大小；格式不支持或结构无法识别时不创建 dst，返回 None。
+/// 只读取各 chunk / 段的头部来确定插入位置，图像数据流式复制，大文件不会整块读入内存
+pub fn embed_file(
+    src: &Path,
+    dst: &Path,
+    format: ImageFormat,
+    provenance: &Provenance,
+) -> io::Result<Option<u64>> {
+    let mut file = BufReader::new(File::open(src)?);
+    let Some(splice) = splice(&mut file, format, provenance)? else {
+        return Ok(None);
+    };
+    let mut out = BufWriter::new(File::create(dst)?);
+    file.rewind()?;
+    io::copy(&mut (&mut file).take(splice.keep), &mut out)?;
+    out.write_all(&splice.insert)?;
+    file.seek(SeekFrom::Start(splice.resume))?;
+    io::copy(&mut (&mut file).take(splice.end - splice.resume), &mut out)?;
+    out.write_all(&splice.append)?;
+    out.flush()?;
+    Ok(Some(splice.len()))
+}
+
+/// 读取图片数据中的标记
+pub fn read(data: &[u8], format: ImageFormat) -> Option<Provenance> {
+    match format {
+        ImageFormat::Png => read_png(data),
+        ImageFormat::Jpeg => read_jpeg(data),
+        ImageFormat::Webp => read_webp(data),
+        ImageFormat::Avif => None,
+    }
+}
+
+/// 读取文件中的标记，按文件内容识别格式。
+/// 只读取标记可能所在的元数据 chunk / 段，图像数据直接跳过，大文件不会整块读入内存
+pub fn detect(path: &Path) -> io::Result<Option<Provenance>> {
+    let Some(format) = ImageFormat::detect(path)? else {
+        return Ok(None);
+    };
+    let mut file = BufReader::new(File::open(path)?);
+    let metadata = match format {
+        ImageFormat::Png => png_metadata(&mut file)?,
+        ImageFormat::Jpeg => jpeg_metadata(&mut file)?,
+        ImageFormat::Webp => webp_metadata(&mut file)?,
+        ImageFormat::Avif => return Ok(None),
+    };
+    Ok(read(&metadata, format))
+}
+
+/// 单个元数据 chunk / 段读取的上限，超出的不会是本工具写入的标记，直接跳过
+const MAX_METADATA: usize = 1 << 20;
+
+/// 填满 buf，文件提前结束时返回 false
+fn read_full(file: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match file.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// 读取 len 字节；超出上限时跳过并返回 None
+fn read_body(file: &mut BufReader<File>, len: usize) -> io::Result<Option<Vec<u8>>> {
+    if len > MAX_METADATA {
+        file.seek_relative(len as i64)?;
+        return Ok(None);
+    }
+    let mut body = vec![0; len];
+    Ok(read_full(file, &mut body)?.then_some(body))
+}
+
+/// 写入标记的方式：输出 = 原数据[..keep] + insert + 原数据[resume..end] + append
+struct Splice {
+    keep: u64,
+    insert: Vec<u8>,
+    resume: u64,
+    end: u64,
+    append: Vec<u8>,
+}
+
+impl Splice {
+    /// 写入标记后的大小
+    fn len(&self) -> u64 {
+        self.keep + self.insert.len() as u64 + (self.end - self.resume) + self.append.len() as u64
+    }
+}
+
+fn splice<R: Read + Seek>(
+    file: &mut BufReader<R>,
+    format: ImageFormat,
+    provenance: &Provenance,
+) -> io::Result<Option<Splice>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    file.rewind()?;
+    match format {
+        ImageFormat::Png => splice_png(file, len, &provenance.to_text()),
+        ImageFormat::Jpeg => splice_jpeg(file, len, &provenance.to_text()),
+        ImageFormat::Webp => splice_webp(file, len, &provenance.to_xmp()),
+        ImageFormat::Avif => Ok(None),
+    }
+}
+
+// ── PNG ────────────────────────────────────────────────────────
+
+/// 依次返回 (类型, 数据)，遇到截断的 chunk 时停止
+fn png_chunks(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 8;
+    std::iter::from_fn(move || {
+        let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind = data.get(pos + 4..pos + 8)?;
+        let body = data.get(pos + 8..(pos + 8).checked_add(len)?)?;
+        pos += 12 + len;
+        Some((kind, body))
+    })
+}
+
+fn splice_png<R: Read + Seek>(
+    file: &mut BufReader<R>,
+    len: u64,
+    text: &str,
+) -> io::Result<Option<Splice>> {
+    // IHDR 必须是第一个 chunk，标记紧随其后
+    let mut head = [0; 16];
+    if !read_full(file, &mut head)? || &head[12..] != b"IHDR" {
+        return Ok(None);
+    }
+    let at = 8 + 12 + u32::from_be_bytes([head[8], head[9], head[10], head[11]]) as u64;
+    if at > len {
+        return Ok(None);
+    }
+
+    let mut payload = TOOL.as_bytes().to_vec();
+    payload.push(0);
+    payload.extend_from_slice(text.as_bytes());
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(b"tEXt");
+    crc.update(&payload);
+
+    let mut insert = Vec::with_capacity(payload.len() + 12);
+    insert.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    insert.extend_from_slice(b"tEXt");
+    insert.extend_from_slice(&payload);
+    insert.extend_from_slice(&crc.finalize().to_be_bytes());
+    Ok(Some(Splice {
+        keep: at,
+        insert,
+        resume: at,
+        end: len,
+        append: Vec::new(),
+    }))
+}
+
+fn read_png(data: &[u8]) -> Option<Provenance> {
+    png_chunks(data).find_map(|(kind, body)| {
+        let nul = body.iter().position(|&b| b == 0)?;
+        if &body[..nul] != TOOL.as_bytes() {
+            return None;
+        }
+        let text = match kind {
+            b"tEXt" => &body[nul + 1..],
+            // iTXt：压缩标志、压缩方法、语言标签、翻译后的关键字之后才是正文，只认未压缩的
+            b"iTXt" => {
+                let rest = body.get(nul + 1..)?;
+                if rest.first() != Some(&0) {
+                    return None;
+                }
+                rest.get(2..)?.splitn(3, |&b| b == 0).nth(2)?
+            }
+            _ => return None,
+        };
+        Provenance::parse_text(std::str::from_utf8(text).ok()?)
+    })
+}
+
+/// 从文件中收集 IDAT 之前的文本 chunk，组成只含这些 chunk 的 PNG 数据供 read_png 解析
+fn png_metadata(file: &mut BufReader<File>) -> io::Result<Vec<u8>> {
+    let mut out = vec![0; 8];
+    if !read_full(file, &mut out)? {
+        return Ok(out);
+    }
+    let mut head = [0; 8];
+    while read_full(file, &mut head)? {
+        let kind = &head[4..];
+        // 标记写在 IHDR 之后，图像数据开始后不再查找
+        if kind == b"IDAT" || kind == b"IEND" {
+            break;
+        }
+        // 数据之后还有 4 字节 CRC
+        let len = u32::from_be_bytes([head[0], head[1], head[2], head[3]]) as usize + 4;
+        if kind != b"tEXt" && kind != b"iTXt" {
+            file.seek_relative(len as i64)?;
+            continue;
+        }
+        if let Some(body) = read_body(file, len)? {
+            out.extend_from_slice(&head);
+            out.extend_from_slice(&body);
+        }
+    }
+    Ok(out)
+}
+
+// ── JPEG ───────────────────────────────────────────────────────
+
+/// 依次返回 SOS（图像数据开始）之前的 (标记, 段数据)
+fn jpeg_segments(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut pos = 2;
+    std::iter::from_fn(move || {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        let body = data.get(pos + 4..pos + 2 + len.max(2))?;
+        pos += 2 + len.max(2);
+        Some((marker, body))
+    })
+}
+
+fn splice_jpeg<R: Read + Seek>(
+    file: &mut BufReader<R>,
+    len: u64,
+    text: &str,
+) -> io::Result<Option<Splice>> {
+    let mut soi = [0; 2];
+    if !read_full(file, &mut soi)? || soi != [0xFF, 0xD8] {
+        return Ok(None);
+    }
+    let Ok(com_len) = u16::try_from(text.len() + 2) else {
+        return Ok(None);
+    };
+    // JFIF / EXIF 等 APPn 段需紧跟 SOI，COM 段放在它们之后
+    let mut at = 2;
+    let mut head = [0; 4];
+    while read_full(file, &mut head)? && head[0] == 0xFF && (0xE0..=0xEF).contains(&head[1]) {
+        let segment = (u16::from_be_bytes([head[2], head[3]]) as u64).max(2);
+        if at + 2 + segment > len {
+            break;
+        }
+        at += 2 + segment;
+        file.seek_relative(segment as i64 - 2)?;
+    }
+
+    let mut insert = vec![0xFF, 0xFE];
+    insert.extend_from_slice(&com_len.to_be_bytes());
+    insert.extend_from_slice(text.as_bytes());
+    Ok(Some(Splice {
+        keep: at,
+        insert,
+        resume: at,
+        end: len,
+        append: Vec::new(),
+    }))
+}
+
+fn read_jpeg(data: &[u8]) -> Option<Provenance> {
+    jpeg_segments(data)
+        .filter(|(marker, _)| *marker == 0xFE)
+        .find_map(|(_, body)| Provenance::parse_text(std::str::from_utf8(body).ok()?))
+}
+
+/// 从文件中收集 SOS 之前的 COM 段，组成只含这些段的 JPEG 数据供 read_jpeg 解析
+fn jpeg_metadata(file: &mut BufReader<File>) -> io::Result<Vec<u8>> {
+    let mut out = vec![0; 2];
+    if !read_full(file, &mut out)? {
+        return Ok(out);
+    }
+    let mut head = [0; 4];
+    while read_full(file, &mut head)? {
+        if head[0] != 0xFF || head[1] == 0xDA || head[1] == 0xD9 {
+            break;
+        }
+        let len = (u16::from_be_bytes([head[2], head[3]]) as usize).max(2) - 2;
+        if head[1] != 0xFE {
+            file.seek_relative(len as i64)?;
+            continue;
+        }
+        if let Some(body) = read_body(file, len)? {
+            out.extend_from_slice(&head);
+            out.extend_from_slice(&body);
+        }
+    }
+    Ok(out)
+}
+
+// ── WebP ───────────────────────────────────────────────────────
+
+/// VP8X 头中的标志位
+const WEBP_ALPHA: u8 = 0x10;
+const WEBP_XMP: u8 = 0x04;
+
+/// 依次返回 RIFF 中的 (FourCC, 数据)，遇到截断的 chunk 时停止
+fn webp_chunks(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 12;
+    std::iter::from_fn(move || {
+        let kind = data.get(pos..pos + 4)?;
+        let len = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        let body = data.get(pos + 8..(pos + 8).checked_add(len)?)?;
+        // chunk 按偶数字节对齐
+        pos += 8 + len + (len & 1);
+        Some((kind, body))
+    })
+}
+
+fn push_webp_chunk(out: &mut Vec<u8>, kind: &[u8], body: &[u8]) {
+    out.extend_from_slice(kind);
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn splice_webp<R: Read + Seek>(
+    file: &mut BufReader<R>,
+    len: u64,
+    xmp: &str,
+) -> io::Result<Option<Splice>> {
+    let mut riff = [0; 12];
+    if !read_full(file, &mut riff)? || &riff[..4] != b"RIFF" || &riff[8..] != b"WEBP" {
+        return Ok(None);
+    }
+    // 逐个 chunk 读取头部：(第一个 chunk 的类型, 数据前 10 字节, 含对齐的结束位置)，
+    // end 为最后一个完整 chunk 的结束位置，截断的 chunk 及之后的数据不保留
+    let mut first: Option<([u8; 4], Vec<u8>, u64)> = None;
+    let mut end = 12;
+    let mut head = [0; 8];
+    while read_full(file, &mut head)? {
+        let size = u32::from_le_bytes([head[4], head[5], head[6], head[7]]) as u64;
+        if end + 8 + size > len {
+            break;
+        }
+        // 已有 XMP（如保留的版权信息）时不覆盖
+        if &head[..4] == b"XMP " {
+            return Ok(None);
+        }
+        let mut skip = size;
+        if first.is_none() {
+            let mut body = vec![0; size.min(10) as usize];
+            file.read_exact(&mut body)?;
+            skip -= body.len() as u64;
+            let first_end = end + 8 + size + (size & 1);
+            first = Some(([head[0], head[1], head[2], head[3]], body, first_end));
+        }
+        // chunk 按偶数字节对齐
+        file.seek_relative((skip + (size & 1)) as i64)?;
+        end += 8 + size + (size & 1);
+    }
+    let Some((kind, body, first_end)) = first else {
+        return Ok(None);
+    };
+
+    // 简单格式（只有 VP8 / VP8L）不能附带元数据，需要补一个 VP8X 头
+    let (header, resume) = match &kind {
+        b"VP8X" if body.len() == 10 => {
+            let mut header = body;
+            header[0] |= WEBP_XMP;
+            (header, first_end.min(len))
+        }
+        b"VP8 " | b"VP8L" => {
+            let Some((width, height, alpha)) = webp_dimensions(&kind, &body) else {
+                return Ok(None);
+            };
+            let (Some(width), Some(height)) = (width.checked_sub(1), height.checked_sub(1)) else {
+                return Ok(None);
+            };
+            let mut header = vec![WEBP_XMP | if alpha { WEBP_ALPHA } else { 0 }, 0, 0, 0];
+            header.extend_from_slice(&width.to_le_bytes()[..3]);
+            header.extend_from_slice(&height.to_le_bytes()[..3]);
+            (header, 12)
+        }
+        _ => return Ok(None),
+    };
+
+    let mut insert = b"RIFF\0\0\0\0WEBP".to_vec();
+    push_webp_chunk(&mut insert, b"VP8X", &header);
+    // 最后一个 chunk 缺少对齐字节时补上；XMP 按规范放在最后
+    let mut append = vec![0; (end - end.min(len)) as usize];
+    push_webp_chunk(&mut append, b"XMP ", xmp.as_bytes());
+    let mut splice = Splice {
+        keep: 0,
+        insert,
+        resume,
+        end: end.min(len),
+        append,
+    };
+    let Ok(riff_len) = u32::try_from(splice.len() - 8) else {
+        return Ok(None);
+    };
+    splice.insert[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    Ok(Some(splice))
+}
+
+/// 从 VP8 / VP8L 位流头读取 (宽, 高, 是否含透明通道)
+fn webp_dimensions(kind: &[u8], body: &[u8]) -> Option<(u32, u32, bool)> {
+    if kind == b"VP8 " {
+        // 帧标签 3 字节 + 起始码 9D 01 2A，之后是 14 位宽高
+        if body.get(3..6)? != [0x9D, 0x01, 0x2A] {
+            return None;
+        }
+        let size = body.get(6..10)?;
+        let width = u16::from_le_bytes([size[0], size[1]]) & 0x3FFF;
+        let height = u16::from_le_bytes([size[2], size[3]]) & 0x3FFF;
+        Some((width as u32, height as u32, false))
+    } else {
+        // 签名 0x2F 之后依次是 14 位宽-1、14 位高-1、1 位透明标志
+        if *body.first()? != 0x2F {
+            return None;
+        }
+        let bits = u32::from_le_bytes(body.get(1..5)?.try_into().ok()?);
+        let width = (bits & 0x3FFF) + 1;
+        let height = ((bits >> 14) & 0x3FFF) + 1;
+        Some((width, height, bits >> 28 & 1 == 1))
+    }
+}
+
+/// 逐个 chunk 查找 XMP，组成只含 XMP chunk 的 WebP 数据供 read_webp 解析
+fn webp_metadata(file: &mut BufReader<File>) -> io::Result<Vec<u8>> {
+    let mut out = vec![0; 12];
+    if !read_full(file, &mut out)? {
+        return Ok(out);
+    }
+    let mut head = [0; 8];
+    while read_full(file, &mut head)? {
+        let len = u32::from_le_bytes([head[4], head[5], head[6], head[7]]) as usize;
+        // chunk 按偶数字节对齐
+        let padded = len + (len & 1);
+        if &head[..4] != b"XMP " {
+            file.seek_relative(padded as i64)?;
+            continue;
+        }
+        if let Some(body) = read_body(file, len)? {
+            push_webp_chunk(&mut out, &head[..4], &body);
+            file.seek_relative((padded - len) as i64)?;
+        }
+    }
+    Ok(out)
+}
+
+fn read_webp(data: &[u8]) -> Option<Provenance> {
+    webp_chunks(data)
+        .filter(|(kind, _)| *kind == b"XMP ")
+        .find_map(|(_, body)| Provenance::parse_xmp(std::str::from_utf8(body).ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 在内存中写入标记，与 embed_file 使用同样的插入位置
+    fn embed(data: &[u8], format: ImageFormat, provenance: &Provenance) -> Option<Vec<u8>> {
+        let splice = splice(
+            &mut BufReader::new(io::Cursor::new(data)),
+            format,
+            provenance,
+        )
+        .ok()??;
+        let mut out = data[..splice.keep as usize].to_vec();
+        out.extend_from_slice(&splice.insert);
+        out.extend_from_slice(&data[splice.resume as usize..splice.end as usize]);
+        out.extend_from_slice(&splice.append);
+        assert_eq!(out.len() as u64, splice.len());
+        Some(out)
+    }
+
+    fn provenance() -> Provenance {
+        Provenance {
+            backend: "tinypng".to_string(),
+            original_size: 123_456,
+        }
+    }
+
+    fn rgba() -> image::RgbaImage {
+        image::RgbaImage::from_fn(33, 17, |x, y| {
+            image::Rgba([x as u8 * 7, y as u8 * 9, 3, if x > 10 { 255 } else { 128 }])
+        })
+    }
+
+    fn encode(image: image::DynamicImage, format: image::ImageFormat) -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut out, format).unwrap();
+        out.into_inner()
+    }
+
+    /// (名称, 数据, 格式)，WebP 覆盖 VP8L、VP8 和带透明通道的 VP8X 三种结构
+    fn samples() -> Vec<(&'static str, Vec<u8>, ImageFormat)> {
+        let rgba = rgba();
+        let rgb = image::DynamicImage::ImageRgba8(rgba.clone()).to_rgb8();
+        vec![
+            (
+                "png",
+                encode(rgba.clone().into(), image::ImageFormat::Png),
+                ImageFormat::Png,
+            ),
+            (
+                "jpeg",
+                encode(rgb.clone().into(), image::ImageFormat::Jpeg),
+                ImageFormat::Jpeg,
+            ),
+            (
+                "vp8l",
+                encode(rgba.clone().into(), image::ImageFormat::WebP),
+                ImageFormat::Webp,
+            ),
+            (
+                "vp8",
+                webp::Encoder::from_rgb(&rgb, 33, 17).encode(70.0).to_vec(),
+                ImageFormat::Webp,
+            ),
+            (
+                "vp8x",
+                webp::Encoder::from_rgba(&rgba, 33, 17)
+                    .encode(70.0)
+                    .to_vec(),
+                ImageFormat::Webp,
+            ),
+        ]
+    }
+
+    fn temp_file(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("tinyimage-marker-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    /// 构造一个 PNG chunk（CRC 不参与解析，填 0）
+    fn png_chunk(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(body);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    #[test]
+    fn embed_read_roundtrip() {
+        for (name, data, format) in samples() {
+            assert_eq!(read(&data, format), None, "{}", name);
+            let marked = embed(&data, format, &provenance()).unwrap();
+            assert_eq!(read(&marked, format), Some(provenance()), "{}", name);
+            // 标记不影响图像内容
+            let before = image::load_from_memory(&data).unwrap().to_rgba8();
+            let after = image::load_from_memory(&marked).unwrap().to_rgba8();
+            assert!(before == after, "{}", name);
+        }
+        assert_eq!(
+            embed(b"\0\0\0\x1cftypavif", ImageFormat::Avif, &provenance()),
+            None
+        );
+    }
+
+    #[test]
+    fn detect_reads_file() {
+        for (name, data, format) in samples() {
+            let plain = temp_file(&format!("{}-plain", name), &data);
+            assert_eq!(detect(&plain).unwrap(), None, "{}", name);
+            let marked = embed(&data, format, &provenance()).unwrap();
+            let path = temp_file(name, &marked);
+            assert_eq!(detect(&path).unwrap(), Some(provenance()), "{}", name);
+        }
+        let other = temp_file("other", b"GIF89a");
+        assert_eq!(detect(&other).unwrap(), None);
+    }
+
+    #[test]
+    fn embed_file_streams() {
+        for (name, data, format) in samples() {
+            let src = temp_file(&format!("{}-src", name), &data);
+            let dst = src.with_extension("marked");
+            let size = embed_file(&src, &dst, format, &provenance()).unwrap();
+            let marked = std::fs::read(&dst).unwrap();
+            assert_eq!(size, Some(marked.len() as u64), "{}", name);
+            assert_eq!(
+                Some(marked),
+                embed(&data, format, &provenance()),
+                "{}",
+                name
+            );
+            assert_eq!(detect(&dst).unwrap(), Some(provenance()), "{}", name);
+        }
+        let avif = temp_file("src.avif", b"\0\0\0\x1cftypavif");
+        let dst = avif.with_extension("marked");
+        assert_eq!(
+            embed_file(&avif, &dst, ImageFormat::Avif, &provenance()).unwrap(),
+            None
+        );
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn truncated_chunk() {
+        for (name, data, format) in samples() {
+            let marked = embed(&data, format, &provenance()).unwrap();
+            // 截断在标记中间：PNG / JPEG 的标记在文件头部，WebP 的 XMP 在末尾
+            let cut = match format {
+                ImageFormat::Webp => marked.len() - 20,
+                _ => 60,
+            };
+            let truncated = &marked[..cut];
+            assert_eq!(read(truncated, format), None, "{}", name);
+            let path = temp_file(&format!("{}-truncated", name), truncated);
+            assert_eq!(detect(&path).unwrap(), None, "{}", name);
+        }
+
+        // 长度字段超出数据范围
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&u32::MAX.to_be_bytes());
+        png.extend_from_slice(b"tEXtTinyImage\0");
+        assert_eq!(read(&png, ImageFormat::Png), None);
+        assert_eq!(detect(&temp_file("long.png", &png)).unwrap(), None);
+
+        // 不足 10 字节的 VP8 chunk 无法读出尺寸，不写入标记
+        for len in [6u32, 7] {
+            let mut webp = b"RIFF\0\0\0\0WEBPVP8 ".to_vec();
+            webp.extend_from_slice(&len.to_le_bytes());
+            webp.extend_from_slice(&[0, 0, 0, 0x9D, 0x01, 0x2A, 0x21][..len as usize]);
+            assert_eq!(embed(&webp, ImageFormat::Webp, &provenance()), None);
+        }
+    }
+
+    #[test]
+    fn itxt_chunk() {
+        let itxt = |compressed: u8| {
+            let mut body = b"TinyImage\0".to_vec();
+            // 压缩标志、压缩方法、空的语言标签和翻译后的关键字
+            body.extend_from_slice(&[compressed, 0, 0, 0]);
+            body.extend_from_slice(provenance().to_text().as_bytes());
+            let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+            png.extend(png_chunk(b"IHDR", &[0; 13]));
+            png.extend(png_chunk(b"iTXt", &body));
+            png.extend(png_chunk(b"IEND", &[]));
+            png
+        };
+        assert_eq!(read(&itxt(0), ImageFormat::Png), Some(provenance()));
+        assert_eq!(read(&itxt(1), ImageFormat::Png), None);
+        assert_eq!(
+            detect(&temp_file("itxt.png", &itxt(0))).unwrap(),
+            Some(provenance())
+        );
+        assert_eq!(detect(&temp_file("itxt-z.png", &itxt(1))).unwrap(), None);
+    }
+}
@@ -0,0 +1,346 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::backend::{self, CompressionBackend};
+use crate::cache;
+use crate::cancel::CancelToken;
+use crate::compress;
+use crate::error::CompressError;
+use crate::format::ImageFormat;
+use crate::settings::{AppSettings, ResizeSpec};
+use crate::verify;
+
+// ── 预估（dry run）──────────────────────────────────────────────
+// 按与实际压缩相同的规则计算输出位置、检查冲突和跳过条件，
+// 并在本地估算压缩后的大小。不访问 TinyPNG、不写入任何文件，不消耗额度
+
+/// 文件在实际压缩时会被如何处理
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanAction {
+    /// 调用后端压缩
+    Compress,
+    /// 复用缓存中之前的输出
+    Reuse,
+    /// 已压缩过或预计节省不足，不会写入
+    Skip,
+    /// 无法压缩（文件不存在、格式不支持、设置有误等）
+    Error,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilePlan {
+    pub path: String,
+    pub action: PlanAction,
+    /// 跳过或无法压缩的原因
+    pub reason: Option<String>,
+    pub input_size: u64,
+    pub input_format: Option<ImageFormat>,
+    /// 扩展名与实际格式不符或没有扩展名
+    pub extension_mismatch: bool,
+    pub output_path: Option<String>,
+    pub output_format: Option<ImageFormat>,
+    /// 原图尺寸，读不出时为 None
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// 预计压缩后的大小；复用缓存时为之前输出的实际大小
+    pub estimated_size: Option<u64>,
+    /// 输出位置的冲突，如会覆盖已有文件、与同批其他文件输出到同一位置
+    pub conflicts: Vec<String>,
+    /// 是否会调用 TinyPNG 并消耗一次额度
+    pub uses_quota: bool,
+}
+
+impl FilePlan {
+    fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            action: PlanAction::Compress,
+            reason: None,
+            input_size: 0,
+            input_format: None,
+            extension_mismatch: false,
+            output_path: None,
+            output_format: None,
+            width: None,
+            height: None,
+            estimated_size: None,
+            conflicts: Vec::new(),
+            uses_quota: false,
+        }
+    }
+}
+
+/// 预估一批文件的压缩结果；批内多个文件输出到同一位置时互相标记冲突
+pub async fn plan(paths: &[String], settings: &AppSettings) -> Vec<FilePlan> {
+    let failed = |path: &str, e: anyhow::Error| FilePlan {
+        action: PlanAction::Error,
+        reason: Some(CompressError::from(e).message().to_string()),
+        ..FilePlan::new(path)
+    };
+    // 后端不可用（如 API Key 未配置）时实际压缩全部会失败
+    let backend = match backend::from_settings(settings) {
+        Ok(backend) => backend,
+        Err(e) => {
+            let error = CompressError::from(e);
+            return paths
+                .iter()
+                .map(|path| failed(path, error.clone().into()))
+                .collect();
+        }
+    };
+
+    let mut plans = Vec::with_capacity(paths.len());
+    for path in paths {
+        let plan = match plan_file(backend.as_ref(), path, settings).await {
+            Ok(plan) => plan,
+            Err(e) => failed(path, e),
+        };
+        plans.push(plan);
+    }
+
+    let mut targets: HashMap<String, usize> = HashMap::new();
+    for plan in plans.iter().filter(|p| writes(p)) {
+        if let Some(output) = &plan.output_path {
+            *targets.entry(output.clone()).or_default() += 1;
+        }
+    }
+    for plan in plans.iter_mut().filter(|p| writes(p)) {
+        let others = plan
+            .output_path
+            .as_ref()
+            .map_or(0, |output| targets[output] - 1);
+        if others > 0 {
+            plan.conflicts.push(format!(
+                "与本批另外 {} 个文件输出到同一位置，后压缩的会覆盖先压缩的",
+                others
+            ));
+        }
+    }
+    plans
+}
+
+fn writes(plan: &FilePlan) -> bool {
+    matches!(plan.action, PlanAction::Compress | PlanAction::Reuse)
+}
+
+/// 单个文件的预估，检查顺序与 compress_with 一致
+async fn plan_file(
+    backend: &dyn CompressionBackend,
+    file_path: &str,
+    settings: &AppSettings,
+) -> Result<FilePlan> {
+    let path = Path::new(file_path);
+    let input = compress::inspect_input(path).await?;
+    compress::validate_resize(settings)?;
+    // 读取图片头部涉及文件 I/O，放到阻塞线程池执行
+    let owned = path.to_path_buf();
+    let (width, height) = tokio::task::spawn_blocking(move || verify::dimensions(&owned))
+        .await
+        .ok()
+        .flatten()
+        .unzip();
+    let mut plan = FilePlan {
+        input_size: input.size,
+        input_format: Some(input.format),
+        extension_mismatch: input.extension_mismatch,
+        width,
+        height,
+        ..FilePlan::new(file_path)
+    };
+
+    if settings.mark_output && compress::recompress_only(input.format, settings) {
+        if let Some(provenance) = compress::read_marker(path).await? {
+            plan.action = PlanAction::Skip;
+            plan.reason = Some(compress::marker_reason(&provenance));
+            return Ok(plan);
+        }
+    }
+    let cache_key = if settings.cache_enabled {
+        Some((
+            compress::hash_file(path).await?,
+            cache::options_id(settings),
+        ))
+    } else {
+        None
+    };
+    if let Some((input_hash, _)) = &cache_key {
+        if compress::recompress_only(input.format, settings) && cache::is_output(input_hash) {
+            plan.action = PlanAction::Skip;
+            plan.reason = Some("已是 TinyImage 压缩过的图片".to_string());
+            return Ok(plan);
+        }
+    }
+
+    let output_format = settings.convert_to.unwrap_or(input.format);
+    let output_path = compress::resolve_output_path(path, output_format, settings)?;
+    plan.output_format = Some(output_format);
+    plan.output_path = Some(output_path.to_string_lossy().into_owned());
+    // 与实际压缩相同：换扩展名时只允许替换之前的压缩结果，其余已有文件会导致压缩失败
+    match compress::check_output_target(path, &output_path, output_format).await {
+        Ok(true) => plan
+            .conflicts
+            .push("输出位置已有同名文件，压缩后会被覆盖".to_string()),
+        Ok(false) => {}
+        Err(e) => {
+            plan.action = PlanAction::Error;
+            plan.reason = Some(CompressError::from(e).message().to_string());
+            return Ok(plan);
+        }
+    }
+
+    // 与实际压缩一样，之前的输出仍在且内容未变时才复用
+    let cached = match &cache_key {
+        Some((input_hash, options_id)) => cache::lookup(input_hash, options_id),
+        None => None,
+    };
+    if let Some(entry) = cached {
+        let intact = matches!(
+            compress::hash_file(Path::new(&entry.output_path)).await,
+            Ok(hash) if hash == entry.output_hash
+        );
+        if intact {
+            plan.action = PlanAction::Reuse;
+            plan.estimated_size = Some(entry.output_size);
+            return Ok(plan);
+        }
+    }
+
+    // 后端无法处理的输入（如本地后端读取 AVIF、动画 WebP）实际压缩时会失败
+    let options = compress::compress_options(input.format, settings, &CancelToken::default());
+    if let Err(e) = backend.check_input(&input.header, &options) {
+        plan.action = PlanAction::Error;
+        plan.reason = Some(CompressError::from(e).message().to_string());
+        return Ok(plan);
+    }
+
+    let estimated = estimate_size(
+        input.size,
+        input.format,
+        output_format,
+        width.zip(height),
+        settings.resize.as_ref(),
+    );
+    plan.estimated_size = Some(estimated);
+    if output_format == input.format {
+        if let Some(reason) = settings.min_savings.shortfall(input.size, estimated) {
+            plan.action = PlanAction::Skip;
+            plan.reason = Some(format!("预计{}", reason));
+            return Ok(plan);
+        }
+    }
+    plan.uses_quota = backend.name() == "tinypng";
+    Ok(plan)
+}
+
+/// 各格式压缩后的典型每像素位数和典型压缩比，取自常见照片 / 截图的压缩结果
+fn typical(format: ImageFormat) -> (f64, f64) {
+    match format {
+        ImageFormat::Png => (3.0, 0.3),
+        ImageFormat::Jpeg => (1.2, 0.6),
+        ImageFormat::Webp => (1.2, 0.5),
+        ImageFormat::Avif => (0.8, 0.4),
+    }
+}
+
+/// 本地估算压缩后大小：按输出像素数 × 典型位数估算，读不出尺寸时按典型压缩比估算。
+/// 结果不超过原图；同格式且不缩放时不低于典型压缩比的一半，避免对小图估得过于乐观
+fn estimate_size(
+    input_size: u64,
+    input_format: ImageFormat,
+    output_format: ImageFormat,
+    dimensions: Option<(u32, u32)>,
+    resize: Option<&ResizeSpec>,
+) -> u64 {
+    let (bits_per_pixel, ratio) = typical(output_format);
+    let estimated = match dimensions {
+        Some((w, h)) => {
+            let (w, h) = resize.map_or((w, h), |spec| spec.target_size(w, h));
+            w as f64 * h as f64 * bits_per_pixel / 8.0
+        }
+        None => input_size as f64 * ratio,
+    };
+    let mut estimated = estimated.min(input_size as f64);
+    if output_format == input_format && resize.is_none() {
+        estimated = estimated.max(input_size as f64 * ratio / 2.0);
+    }
+    estimated.round() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::ResizeMethod;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn estimate_by_pixels() {
+        // 1000×1000 的 PNG 按每像素 3 位估算
+        let estimated = estimate_size(
+            MB,
+            ImageFormat::Png,
+            ImageFormat::Png,
+            Some((1000, 1000)),
+            None,
+        );
+        assert_eq!(estimated, 375_000);
+        // 转换格式时按输出格式的典型位数估算
+        let webp = estimate_size(
+            MB,
+            ImageFormat::Png,
+            ImageFormat::Webp,
+            Some((1000, 1000)),
+            None,
+        );
+        assert_eq!(webp, 150_000);
+    }
+
+    #[test]
+    fn estimate_without_dimensions() {
+        let estimated = estimate_size(MB, ImageFormat::Jpeg, ImageFormat::Jpeg, None, None);
+        assert_eq!(estimated, (MB as f64 * 0.6).round() as u64);
+    }
+
+    #[test]
+    fn estimate_never_exceeds_input() {
+        // 像素多但原图已很小（如大面积纯色），估算值不超过原图
+        let estimated = estimate_size(
+            10_000,
+            ImageFormat::Png,
+            ImageFormat::Png,
+            Some((4000, 3000)),
+            None,
+        );
+        assert_eq!(estimated, 10_000);
+    }
+
+    #[test]
+    fn estimate_floor_for_same_format() {
+        // 小图按像素估得过低时，不低于典型压缩比的一半
+        let estimated = estimate_size(
+            MB,
+            ImageFormat::Png,
+            ImageFormat::Png,
+            Some((100, 100)),
+            None,
+        );
+        assert_eq!(estimated, (MB as f64 * 0.3 / 2.0).round() as u64);
+        // 缩放后像素确实变少，不受此下限约束
+        let resize = ResizeSpec {
+            method: ResizeMethod::Fit,
+            width: Some(10),
+            height: Some(10),
+        };
+        let resized = estimate_size(
+            MB,
+            ImageFormat::Png,
+            ImageFormat::Png,
+            Some((100, 100)),
+            Some(&resize),
+        );
+        assert_eq!(resized, 38);
+    }
+}
@@ -1,8 +1,10 @@
-use anyhow::Result;
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
 
+use crate::format::ImageFormat;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Theme {
@@ -14,19 +16,473 @@ pub enum Theme {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AppSettings {
+    /// 旧版单个 Key，仅用于读取旧配置，加载时迁移到 api_keys
+    #[serde(default, skip_serializing)]
     pub api_key: String,
+    /// TinyPNG API Key 列表，当前 Key 额度用完时按顺序切换到下一个
+    #[serde(default)]
+    pub api_keys: Vec<ApiKey>,
+    /// TinyPNG 兼容服务地址，可指向自建服务或本地 mock
+    #[serde(default = "default_api_endpoint")]
+    pub api_endpoint: String,
+    /// 每月压缩限额，接近时提醒；0 表示不提醒
+    #[serde(default = "default_monthly_limit")]
+    pub monthly_limit: u32,
     pub notify_mode: NotifyMode,
     pub output_mode: OutputMode,
     pub output_directory: String,
     pub context_menu_enabled: bool,
     #[serde(default = "default_theme")]
     pub theme: Theme,
+    #[serde(default = "default_backend")]
+    pub backend: BackendKind,
+    #[serde(default)]
+    pub local_png: LocalPngSettings,
+    #[serde(default)]
+    pub local_jpeg: LocalJpegSettings,
+    #[serde(default)]
+    pub local_webp: LocalWebpSettings,
+    #[serde(default)]
+    pub local_avif: LocalAvifSettings,
+    /// 输出格式，None 表示保持原格式
+    #[serde(default)]
+    pub convert_to: Option<ImageFormat>,
+    /// 透明图片转为 JPEG 时的背景色（white / black / #rrggbb）
+    #[serde(default = "default_convert_background")]
+    pub convert_background: String,
+    /// 缩放参数，None 表示保持原尺寸
+    #[serde(default)]
+    pub resize: Option<ResizeSpec>,
+    #[serde(default)]
+    pub preserve: PreserveSettings,
+    #[serde(default)]
+    pub retry: RetrySettings,
+    #[serde(default)]
+    pub proxy: ProxySettings,
+    #[serde(default)]
+    pub network: NetworkSettings,
+    #[serde(default)]
+    pub min_savings: MinSavingsSettings,
+    /// 按内容哈希缓存压缩结果：跳过已压缩过的图片，相同图片复用之前的输出
+    #[serde(default = "default_cache_enabled")]
+    pub cache_enabled: bool,
+    /// 在压缩结果中写入 TinyImage 标记（原始大小、所用后端），带标记的文件不再重复压缩
+    #[serde(default = "default_mark_output")]
+    pub mark_output: bool,
+}
+
+/// 带备注的 API Key，团队共用多个免费 Key 时便于区分
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ApiKey {
+    pub label: String,
+    pub key: String,
+}
+
+impl ApiKey {
+    /// 展示用名称：有备注用备注，否则只显示 Key 末尾 4 位
+    pub fn display_name(&self) -> String {
+        if !self.label.trim().is_empty() {
+            return self.label.trim().to_string();
+        }
+        let start = self.key.char_indices().rev().nth(3).map_or(0, |(i, _)| i);
+        format!("****{}", &self.key[start..])
+    }
+}
+
+impl AppSettings {
+    /// 可用的 API Key（去掉空 Key），按轮换顺序排列
+    pub fn active_keys(&self) -> Vec<ApiKey> {
+        let mut keys: Vec<ApiKey> = self
+            .api_keys
+            .iter()
+            .filter(|k| !k.key.trim().is_empty())
+            .map(|k| ApiKey {
+                label: k.label.clone(),
+                key: k.key.trim().to_string(),
+            })
+            .collect();
+        // 兼容仍只传单个 Key 的调用方
+        if keys.is_empty() && !self.api_key.trim().is_empty() {
+            keys.push(ApiKey {
+                label: String::new(),
+                key: self.api_key.trim().to_string(),
+            });
+        }
+        keys
+    }
+
+    /// 把旧版单个 api_key 迁移到 api_keys
+    fn migrate(&mut self) {
+        let legacy = std::mem::take(&mut self.api_key);
+        if self.api_keys.is_empty() && !legacy.trim().is_empty() {
+            self.api_keys.push(ApiKey {
+                label: String::new(),
+                key: legacy.trim().to_string(),
+            });
+        }
+    }
+}
+
+fn default_api_endpoint() -> String {
+    "https://api.tinify.com".to_string()
+}
+
+fn default_monthly_limit() -> u32 {
+    500
 }
 
 fn default_theme() -> Theme {
     Theme::Auto
 }
 
+fn default_convert_background() -> String {
+    "white".to_string()
+}
+
+fn default_cache_enabled() -> bool {
+    true
+}
+
+fn default_mark_output() -> bool {
+    true
+}
+
+fn default_backend() -> BackendKind {
+    BackendKind::TinyPng
+}
+
+/// 压缩后端类型
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    TinyPng,
+    /// 本地离线压缩，不消耗配额
+    Local,
+}
+
+/// 缩放方式，与 TinyPNG resize 接口一致
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResizeMethod {
+    /// 按比例缩放，只指定宽或高之一
+    Scale,
+    /// 等比缩放至不超过给定宽高
+    Fit,
+    /// 等比缩放后裁剪为给定宽高
+    Cover,
+    /// 生成缩略图，裁剪时尽量保留主体
+    Thumb,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResizeSpec {
+    pub method: ResizeMethod,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl ResizeSpec {
+    pub fn validate(&self) -> Result<()> {
+        if self.width == Some(0) || self.height == Some(0) {
+            bail!("缩放尺寸必须大于 0");
+        }
+        match (self.method, self.width, self.height) {
+            (ResizeMethod::Scale, Some(_), None) | (ResizeMethod::Scale, None, Some(_)) => Ok(()),
+            (ResizeMethod::Scale, _, _) => bail!("按比例缩放需要且只能指定宽度或高度之一"),
+            (_, Some(_), Some(_)) => Ok(()),
+            _ => bail!("该缩放方式需要同时指定宽度和高度"),
+        }
+    }
+
+    /// 缩放后的尺寸，与 TinyPNG 的缩放语义一致：scale / fit 只缩小不放大，
+    /// cover / thumb 裁剪为给定宽高。本地后端与预估共用，保证两者结果一致
+    pub fn target_size(&self, w: u32, h: u32) -> (u32, u32) {
+        let scale = |target: u32, from: u32, other: u32| {
+            ((other as u64 * target as u64) / from.max(1) as u64).max(1) as u32
+        };
+        match self.method {
+            ResizeMethod::Scale => match (self.width, self.height) {
+                (Some(tw), _) if tw < w => (tw, scale(tw, w, h)),
+                (None, Some(th)) if th < h => (scale(th, h, w), th),
+                _ => (w, h),
+            },
+            ResizeMethod::Fit => {
+                let (tw, th) = (self.width.unwrap_or(w), self.height.unwrap_or(h));
+                if w <= tw && h <= th {
+                    (w, h)
+                } else if w as u64 * th as u64 > h as u64 * tw as u64 {
+                    (tw, scale(tw, w, h))
+                } else {
+                    (scale(th, h, w), th)
+                }
+            }
+            ResizeMethod::Cover | ResizeMethod::Thumb => {
+                (self.width.unwrap_or(w), self.height.unwrap_or(h))
+            }
+        }
+    }
+}
+
+/// 可保留的元数据类型，与 TinyPNG preserve 接口一致
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataField {
+    /// 版权信息
+    Copyright,
+    /// 拍摄 / 创建时间
+    Creation,
+    /// GPS 位置
+    Location,
+}
+
+/// 压缩时保留的元数据（默认全部去除）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PreserveSettings {
+    pub copyright: bool,
+    pub creation: bool,
+    pub location: bool,
+}
+
+impl PreserveSettings {
+    pub fn fields(&self) -> Vec<MetadataField> {
+        [
+            (self.copyright, MetadataField::Copyright),
+            (self.creation, MetadataField::Creation),
+            (self.location, MetadataField::Location),
+        ]
+        .into_iter()
+        .filter_map(|(on, field)| on.then_some(field))
+        .collect()
+    }
+}
+
+/// 网络请求失败时的重试策略，上传和下载阶段分别计算次数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetrySettings {
+    /// 每个阶段的最大尝试次数（含首次），1 表示不重试
+    pub max_attempts: u32,
+    /// 首次重试前的等待时间（毫秒），之后每次翻倍
+    pub base_delay_ms: u64,
+    /// 单次等待时间上限（毫秒）
+    pub max_delay_ms: u64,
+    /// 随机缩短等待时间，避免并发任务同时重试
+    pub jitter: bool,
+    /// 可重试的 HTTP 状态码；429 表示额度用完，由 Key 轮换处理
+    pub retry_statuses: Vec<u16>,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_ms: 1000,
+            max_delay_ms: 10_000,
+            jitter: true,
+            retry_statuses: vec![408, 500, 502, 503, 504],
+        }
+    }
+}
+
+/// 代理模式
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    /// 使用系统代理和 HTTP_PROXY / HTTPS_PROXY / NO_PROXY 环境变量
+    #[default]
+    System,
+    /// 直连，忽略系统代理
+    None,
+    /// 使用下方手动填写的代理
+    Manual,
+}
+
+/// 访问 TinyPNG 时使用的代理
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProxySettings {
+    pub mode: ProxyMode,
+    /// 代理地址，支持 http://、https://、socks5://、socks5h://
+    pub url: String,
+    /// 代理认证，用户名为空表示无需认证
+    pub username: String,
+    pub password: String,
+    /// 不走代理的主机，逗号分隔，如 localhost, .corp.example.com
+    pub no_proxy: String,
+}
+
+/// 访问 TinyPNG 时的超时和限速
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NetworkSettings {
+    /// 建立连接的超时（秒），0 表示使用系统默认
+    pub connect_timeout_secs: u64,
+    /// 单次请求从发出到读完响应的总超时（秒），0 表示不限制；
+    /// 慢速网络下上传大图可能需要调大。设置了限速的上传 / 下载不受此限制
+    pub timeout_secs: u64,
+    /// 传输中连续多久没有数据进出即判定连接卡住（秒），0 表示不检测；
+    /// 按限速等待的时间不计入，限速的传输靠它发现卡住的连接
+    pub idle_timeout_secs: u64,
+    /// 上传限速（KB/s），所有并发任务合计，0 表示不限速
+    pub upload_limit_kbps: u64,
+    /// 下载限速（KB/s），所有并发任务合计，0 表示不限速
+    pub download_limit_kbps: u64,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self {
+            connect_timeout_secs: 30,
+            timeout_secs: 120,
+            idle_timeout_secs: 60,
+            upload_limit_kbps: 0,
+            download_limit_kbps: 0,
+        }
+    }
+}
+
+/// 节省不足时跳过写入，保留原图；两项都设置时须同时满足
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MinSavingsSettings {
+    /// 最少节省的比例（%），0 表示不限制
+    pub percent: f64,
+    /// 最少节省的体积（KB），0 表示不限制
+    pub kb: u64,
+}
+
+impl MinSavingsSettings {
+    /// 未达到门槛时返回跳过原因
+    pub fn shortfall(&self, input_size: u64, output_size: u64) -> Option<String> {
+        if self.percent <= 0.0 && self.kb == 0 {
+            return None;
+        }
+        if output_size >= input_size {
+            return Some("压缩后体积没有减小".to_string());
+        }
+        let saved = input_size - output_size;
+        let percent = saved as f64 * 100.0 / input_size as f64;
+        if percent < self.percent {
+            return Some(format!(
+                "仅节省 {:.1}%，低于设定的 {}%",
+                percent, self.percent
+            ));
+        }
+        if saved < self.kb * 1024 {
+            return Some(format!(
+                "仅节省 {:.1} KB，低于设定的 {} KB",
+                saved as f64 / 1024.0,
+                self.kb
+            ));
+        }
+        None
+    }
+}
+
+/// 本地 PNG 有损压缩参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LocalPngSettings {
+    /// 调色板最大颜色数（2-256）
+    pub max_colors: u16,
+    /// 量化采样步长（1-30，越小质量越好、速度越慢）
+    pub quantize_speed: u8,
+    /// oxipng 优化级别（0-6）
+    pub optimize_level: u8,
+}
+
+impl Default for LocalPngSettings {
+    fn default() -> Self {
+        Self {
+            max_colors: 256,
+            quantize_speed: 10,
+            optimize_level: 2,
+        }
+    }
+}
+
+/// 本地 JPEG 重新编码参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LocalJpegSettings {
+    /// 质量（1-100）
+    pub quality: u8,
+    pub chroma_subsampling: ChromaSubsampling,
+    /// 渐进式输出
+    pub progressive: bool,
+}
+
+impl Default for LocalJpegSettings {
+    fn default() -> Self {
+        Self {
+            quality: 75,
+            chroma_subsampling: ChromaSubsampling::Yuv420,
+            progressive: true,
+        }
+    }
+}
+
+/// 本地 WebP 编码参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LocalWebpSettings {
+    /// 无损模式
+    pub lossless: bool,
+    /// 质量（0-100），无损模式下表示压缩力度
+    pub quality: u8,
+    /// 压缩方法（0-6，越大越慢、体积越小）
+    pub method: u8,
+    /// 透明通道质量（0-100）
+    pub alpha_quality: u8,
+}
+
+impl Default for LocalWebpSettings {
+    fn default() -> Self {
+        Self {
+            lossless: false,
+            quality: 75,
+            method: 4,
+            alpha_quality: 100,
+        }
+    }
+}
+
+/// 本地 AVIF 编码参数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LocalAvifSettings {
+    /// 质量（1-100）
+    pub quality: u8,
+    /// 编码速度（1-10，越小越慢、体积越小）
+    pub speed: u8,
+    /// 透明通道质量（1-100）
+    pub alpha_quality: u8,
+}
+
+impl Default for LocalAvifSettings {
+    fn default() -> Self {
+        Self {
+            quality: 70,
+            speed: 6,
+            alpha_quality: 80,
+        }
+    }
+}
+
+/// 色度抽样方式
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum ChromaSubsampling {
+    #[serde(rename = "420")]
+    Yuv420,
+    #[serde(rename = "422")]
+    Yuv422,
+    #[serde(rename = "444")]
+    Yuv444,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum NotifyMode {
@@ -47,6 +503,9 @@ impl Default for AppSettings {
     fn default() -> Self {
         Self {
             api_key: String::new(),
+            api_keys: Vec::new(),
+            api_endpoint: default_api_endpoint(),
+            monthly_limit: default_monthly_limit(),
             notify_mode: NotifyMode::Notification,
             output_mode: OutputMode::Alongside,
             output_directory: String::new(),
@@ -54,24 +513,45 @@ impl Default for AppSettings {
             // Windows：需要手动写注册表才能启用，默认关闭
             context_menu_enabled: cfg!(target_os = "macos"),
             theme: Theme::Auto,
+            backend: BackendKind::TinyPng,
+            local_png: LocalPngSettings::default(),
+            local_jpeg: LocalJpegSettings::default(),
+            local_webp: LocalWebpSettings::default(),
+            local_avif: LocalAvifSettings::default(),
+            convert_to: None,
+            convert_background: default_convert_background(),
+            resize: None,
+            preserve: PreserveSettings::default(),
+            retry: RetrySettings::default(),
+            proxy: ProxySettings::default(),
+            network: NetworkSettings::default(),
+            min_savings: MinSavingsSettings::default(),
+            cache_enabled: default_cache_enabled(),
+            mark_output: default_mark_output(),
         }
     }
 }
 
-fn config_path() -> PathBuf {
+/// TinyImage 的配置目录，设置和统计数据都存放在这里
+pub fn config_dir() -> PathBuf {
     dirs::config_dir()
         .unwrap_or_else(|| PathBuf::from("."))
         .join("TinyImage")
-        .join("settings.json")
+}
+
+fn config_path() -> PathBuf {
+    config_dir().join("settings.json")
 }
 
 pub fn load() -> AppSettings {
     let path = config_path();
-    if let Ok(data) = fs::read_to_string(&path) {
+    let mut settings: AppSettings = if let Ok(data) = fs::read_to_string(&path) {
         serde_json::from_str(&data).unwrap_or_default()
     } else {
         AppSettings::default()
-    }
+    };
+    settings.migrate();
+    settings
 }
 
 pub fn save(settings: &AppSettings) -> Result<()> {
@@ -83,3 +563,97 @@ pub fn save(settings: &AppSettings) -> Result<()> {
     fs::write(path, data)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(method: ResizeMethod, width: Option<u32>, height: Option<u32>) -> ResizeSpec {
+        ResizeSpec {
+            method,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn scale_keeps_aspect_ratio() {
+        let by_width = spec(ResizeMethod::Scale, Some(400), None);
+        assert_eq!(by_width.target_size(800, 600), (400, 300));
+        let by_height = spec(ResizeMethod::Scale, None, Some(300));
+        assert_eq!(by_height.target_size(800, 600), (400, 300));
+        // 只缩小不放大
+        assert_eq!(by_width.target_size(200, 100), (200, 100));
+        // 极细长的图缩放后另一边至少 1 像素
+        assert_eq!(by_width.target_size(10_000, 1), (400, 1));
+    }
+
+    #[test]
+    fn fit_within_box() {
+        let fit = spec(ResizeMethod::Fit, Some(400), Some(400));
+        assert_eq!(fit.target_size(800, 600), (400, 300));
+        assert_eq!(fit.target_size(600, 800), (300, 400));
+        assert_eq!(fit.target_size(300, 200), (300, 200));
+    }
+
+    #[test]
+    fn cover_and_thumb_use_given_size() {
+        for method in [ResizeMethod::Cover, ResizeMethod::Thumb] {
+            let target = spec(method, Some(150), Some(100));
+            assert_eq!(target.target_size(800, 600), (150, 100));
+            assert_eq!(target.target_size(50, 50), (150, 100));
+        }
+    }
+
+    fn min_savings(percent: f64, kb: u64) -> MinSavingsSettings {
+        MinSavingsSettings { percent, kb }
+    }
+
+    #[test]
+    fn shortfall_disabled() {
+        let off = MinSavingsSettings::default();
+        assert_eq!(off.shortfall(1000, 999), None);
+        // 未设门槛时即使变大也照常写入
+        assert_eq!(off.shortfall(1000, 2000), None);
+    }
+
+    #[test]
+    fn shortfall_percent() {
+        let threshold = min_savings(10.0, 0);
+        assert_eq!(threshold.shortfall(1000, 900), None);
+        assert_eq!(
+            threshold.shortfall(1000, 950).as_deref(),
+            Some("仅节省 5.0%，低于设定的 10%")
+        );
+    }
+
+    #[test]
+    fn shortfall_kb() {
+        let threshold = min_savings(0.0, 10);
+        assert_eq!(threshold.shortfall(100 * 1024, 90 * 1024), None);
+        assert_eq!(
+            threshold.shortfall(100 * 1024, 95 * 1024).as_deref(),
+            Some("仅节省 5.0 KB，低于设定的 10 KB")
+        );
+    }
+
+    #[test]
+    fn shortfall_requires_both() {
+        let threshold = min_savings(10.0, 10);
+        // 比例够但体积不够
+        assert!(threshold.shortfall(20 * 1024, 12 * 1024).is_some());
+        // 体积够但比例不够
+        assert!(threshold.shortfall(1024 * 1024, 1000 * 1024).is_some());
+        assert_eq!(threshold.shortfall(1024 * 1024, 512 * 1024), None);
+    }
+
+    #[test]
+    fn shortfall_larger_or_empty() {
+        let threshold = min_savings(1.0, 0);
+        let grown = Some("压缩后体积没有减小");
+        assert_eq!(threshold.shortfall(1000, 1200).as_deref(), grown);
+        assert_eq!(threshold.shortfall(1000, 1000).as_deref(), grown);
+        // 空文件不会除以零
+        assert_eq!(threshold.shortfall(0, 0).as_deref(), grown);
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::settings;
+
+// ── TinyPNG 月度压缩次数 ───────────────────────────────────────
+// 每次 /shrink 响应的 Compression-Count 头即该 Key 本月累计次数，
+// 按 Key 指纹 + 月份持久化，供设置页展示和限额提醒使用
+
+// 串行化读-改-写，避免并发压缩互相覆盖
+static FILE_LOCK: Mutex<()> = Mutex::new(());
+// 本次运行中已提醒过的 (Key 指纹, 月份)，避免每张图片都提醒
+static WARNED: Mutex<Option<HashSet<(String, String)>>> = Mutex::new(None);
+
+/// 超过限额的该比例时开始提醒
+const WARN_RATIO: f64 = 0.9;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KeyUsage {
+    /// 统计月份，格式 YYYY-MM（UTC）
+    pub month: String,
+    pub count: u32,
+    /// 本月额度已用完（收到过 429 / TooManyRequests）
+    #[serde(default)]
+    pub exhausted: bool,
+}
+
+fn usage_path() -> PathBuf {
+    settings::config_dir().join("usage.json")
+}
+
+fn load_all() -> HashMap<String, KeyUsage> {
+    fs::read_to_string(usage_path())
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Key 的指纹，避免在统计文件中再存一份明文 Key
+fn key_id(api_key: &str) -> String {
+    Sha256::digest(api_key.as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// 记录 TinyPNG 返回的本月累计次数；并发请求可能乱序返回，同月只增不减
+pub fn record(api_key: &str, count: u32) -> Result<()> {
+    update(api_key, |entry| entry.count = entry.count.max(count))
+}
+
+/// 标记 Key 本月额度是否已用完，轮换时优先使用未用完的 Key
+pub fn set_exhausted(api_key: &str, exhausted: bool) -> Result<()> {
+    if get(api_key).exhausted == exhausted {
+        return Ok(());
+    }
+    update(api_key, |entry| entry.exhausted = exhausted)
+}
+
+/// 读-改-写该 Key 本月的统计，跨月时先归零
+fn update(api_key: &str, f: impl FnOnce(&mut KeyUsage)) -> Result<()> {
+    let _guard = FILE_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut all = load_all();
+    let month = current_month();
+    let entry = all.entry(key_id(api_key)).or_default();
+    if entry.month != month {
+        *entry = KeyUsage {
+            month,
+            ..Default::default()
+        };
+    }
+    f(entry);
+
+    let path = usage_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(&all)?)?;
+    Ok(())
+}
+
+/// 查询本月已用次数，跨月后自动归零
+pub fn get(api_key: &str) -> KeyUsage {
+    let month = current_month();
+    match load_all().remove(&key_id(api_key)) {
+        Some(usage) if usage.month == month => usage,
+        _ => KeyUsage {
+            month,
+            ..Default::default()
+        },
+    }
+}
+
+/// 是否需要提醒接近月度限额；同一 Key 每月每次运行只提醒一次
+pub fn should_warn(api_key: &str, count: u32, limit: u32) -> bool {
+    if limit == 0 || (count as f64) < limit as f64 * WARN_RATIO {
+        return false;
+    }
+    let mut warned = WARNED.lock().unwrap_or_else(|e| e.into_inner());
+    warned
+        .get_or_insert_with(HashSet::new)
+        .insert((key_id(api_key), current_month()))
+}
+
+/// 当前 UTC 月份（YYYY-MM），TinyPNG 按自然月重置计数
+fn current_month() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    month_of(secs)
+}
+
+/// Unix 秒所在的 UTC 月份（YYYY-MM）
+fn month_of(secs: u64) -> String {
+    // 公历换算（Howard Hinnant civil_from_days）
+    let z = (secs / 86_400) as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}", year, month)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn month_boundaries() {
+        assert_eq!(month_of(0), "1970-01");
+        // 2024-01-31 23:59:59 / 2024-02-01 00:00:00
+        assert_eq!(month_of(1_706_745_599), "2024-01");
+        assert_eq!(month_of(1_706_745_600), "2024-02");
+        // 闰年 2 月 29 日：2024-02-29 23:59:59 / 2024-03-01 00:00:00
+        assert_eq!(month_of(1_709_251_199), "2024-02");
+        assert_eq!(month_of(1_709_251_200), "2024-03");
+        // 平年 2 月只有 28 天：2023-02-28 23:59:59 / 2023-03-01 00:00:00
+        assert_eq!(month_of(1_677_628_799), "2023-02");
+        assert_eq!(month_of(1_677_628_800), "2023-03");
+    }
+
+    #[test]
+    fn year_boundaries() {
+        // 2023-12-31 23:59:59 / 2024-01-01 00:00:00
+        assert_eq!(month_of(1_704_067_199), "2023-12");
+        assert_eq!(month_of(1_704_067_200), "2024-01");
+        // 世纪年：2000 是闰年（2 月 29 日），2100 不是（2 月 28 日次日即 3 月）
+        assert_eq!(month_of(951_782_400), "2000-02");
+        assert_eq!(month_of(4_107_542_399), "2100-02");
+        assert_eq!(month_of(4_107_542_400), "2100-03");
+        // 2099-12-31 23:59:59 / 2100-01-01 00:00:00
+        assert_eq!(month_of(4_102_444_799), "2099-12");
+        assert_eq!(month_of(4_102_444_800), "2100-01");
+    }
+}
@@ -0,0 +1,200 @@
+use std::path::Path;
+
+use crate::error::CompressError;
+use crate::format::ImageFormat;
+
+// ── 输出校验 ───────────────────────────────────────────────────
+// 替换原图前确认临时文件是一张完整的图片：大小与后端写入的一致、
+// 格式正确、文件结构没有被截断、能正常解码且尺寸符合预期。
+// 任何一项不通过都返回 VerifyFailed，临时文件随之删除，原图保持不变
+
+/// 对压缩结果的预期
+pub struct Expected {
+    pub format: ImageFormat,
+    /// 后端报告写入的字节数
+    pub size: u64,
+    /// 预期尺寸，None 表示不检查
+    pub dimensions: Option<(u32, u32)>,
+    /// 允许宽高互换：按 EXIF 方向旋转后宽高会对调
+    pub allow_rotated: bool,
+}
+
+pub fn verify(path: &Path, expected: &Expected) -> Result<(), CompressError> {
+    let data = std::fs::read(path)
+        .map_err(|e| CompressError::VerifyFailed(format!("无法读取压缩结果: {}", e)))?;
+
+    if data.len() as u64 != expected.size {
+        return Err(CompressError::VerifyFailed(format!(
+            "压缩结果大小不符：应为 {} 字节，实际 {} 字节",
+            expected.size,
+            data.len()
+        )));
+    }
+
+    let format = ImageFormat::sniff(&data);
+    if format != Some(expected.format) {
+        return Err(CompressError::VerifyFailed(format!(
+            "压缩结果格式不符：应为 {}，实际为 {}",
+            expected.format.extension().to_uppercase(),
+            format.map_or("未知格式".to_string(), |f| f.extension().to_uppercase())
+        )));
+    }
+
+    if !is_complete(&data, expected.format) {
+        return Err(CompressError::VerifyFailed(
+            "压缩结果不完整，文件可能被截断".to_string(),
+        ));
+    }
+
+    // AVIF 解码依赖系统 dav1d，只做结构检查
+    let Some(image_format) = decodable(expected.format) else {
+        return Ok(());
+    };
+    let image = image::load_from_memory_with_format(&data, image_format)
+        .map_err(|e| CompressError::VerifyFailed(format!("压缩结果无法解码: {}", e)))?;
+
+    if let Some((w, h)) = expected.dimensions {
+        let actual = (image.width(), image.height());
+        if actual != (w, h) && !(expected.allow_rotated && actual == (h, w)) {
+            return Err(CompressError::VerifyFailed(format!(
+                "压缩结果尺寸不符：应为 {}×{}，实际 {}×{}",
+                w, h, actual.0, actual.1
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// 读取原图尺寸（按内容识别格式），读不出时返回 None
+pub fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    image::ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+fn decodable(format: ImageFormat) -> Option<image::ImageFormat> {
+    match format {
+        ImageFormat::Png => Some(image::ImageFormat::Png),
+        ImageFormat::Jpeg => Some(image::ImageFormat::Jpeg),
+        ImageFormat::Webp => Some(image::ImageFormat::WebP),
+        ImageFormat::Avif => None,
+    }
+}
+
+/// 按各格式的结束标记或长度字段检查文件是否完整；
+/// 部分解码器遇到截断的数据会补灰而不报错，不能只靠解码判断
+fn is_complete(data: &[u8], format: ImageFormat) -> bool {
+    match format {
+        // 最后一个 chunk 必须是 IEND（长度 0 + 类型 + CRC）
+        ImageFormat::Png => data.ends_with(b"\0\0\0\0IEND\xAE\x42\x60\x82"),
+        // 以 EOI 标记结束
+        ImageFormat::Jpeg => data.ends_with(&[0xFF, 0xD9]),
+        // RIFF 头记录的长度不含开头 8 字节
+        ImageFormat::Webp => {
+            data.len() >= 12
+                && u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize + 8
+                    == data.len()
+        }
+        // 顶层 box 的长度之和恰好等于文件长度
+        ImageFormat::Avif => {
+            let mut pos = 0usize;
+            while pos < data.len() {
+                let Some(header) = data.get(pos..pos + 8) else {
+                    return false;
+                };
+                let size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+                    // 0 表示延续到文件末尾
+                    0 => data.len() - pos,
+                    // 1 表示使用紧随其后的 64 位长度
+                    1 => match data.get(pos + 8..pos + 16) {
+                        Some(large) => {
+                            u64::from_be_bytes(large.try_into().unwrap_or_default()) as usize
+                        }
+                        None => return false,
+                    },
+                    size => size as usize,
+                };
+                if size < 8 {
+                    return false;
+                }
+                pos = pos.saturating_add(size);
+            }
+            pos == data.len()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn avif_box(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    #[test]
+    fn png_and_jpeg_end_markers() {
+        let mut png = Vec::new();
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        assert!(is_complete(&png, ImageFormat::Png));
+        assert!(!is_complete(&png[..png.len() - 1], ImageFormat::Png));
+
+        let mut jpeg = Vec::new();
+        image::DynamicImage::new_rgb8(4, 4)
+            .write_to(
+                &mut std::io::Cursor::new(&mut jpeg),
+                image::ImageFormat::Jpeg,
+            )
+            .unwrap();
+        assert!(is_complete(&jpeg, ImageFormat::Jpeg));
+        assert!(!is_complete(&jpeg[..jpeg.len() - 2], ImageFormat::Jpeg));
+    }
+
+    #[test]
+    fn webp_riff_length() {
+        let mut webp = b"RIFF\x0c\0\0\0WEBPVP8L\0\0\0\0".to_vec();
+        assert!(is_complete(&webp, ImageFormat::Webp));
+        webp.pop();
+        assert!(!is_complete(&webp, ImageFormat::Webp));
+        assert!(!is_complete(b"RIFF\x04\0\0", ImageFormat::Webp));
+    }
+
+    #[test]
+    fn avif_top_level_boxes() {
+        let ftyp = avif_box(b"ftyp", b"avif\0\0\0\0avifmif1");
+        let mut avif = ftyp.clone();
+        avif.extend(avif_box(b"meta", &[0; 20]));
+        avif.extend(avif_box(b"mdat", &[0; 100]));
+        assert!(is_complete(&avif, ImageFormat::Avif));
+        // 截断在 box 数据中间、box 头中间
+        assert!(!is_complete(&avif[..avif.len() - 1], ImageFormat::Avif));
+        assert!(!is_complete(&avif[..ftyp.len() + 4], ImageFormat::Avif));
+
+        // 长度 0：延续到文件末尾
+        let mut to_end = ftyp.clone();
+        to_end.extend_from_slice(b"\0\0\0\0mdat");
+        to_end.extend_from_slice(&[0; 50]);
+        assert!(is_complete(&to_end, ImageFormat::Avif));
+
+        // 长度 1：使用 64 位长度
+        let mut large = ftyp.clone();
+        large.extend_from_slice(b"\0\0\0\x01mdat");
+        large.extend_from_slice(&26u64.to_be_bytes());
+        large.extend_from_slice(&[0; 10]);
+        assert!(is_complete(&large, ImageFormat::Avif));
+        assert!(!is_complete(&large[..ftyp.len() + 12], ImageFormat::Avif));
+
+        // 长度小于 box 头
+        let mut invalid = ftyp;
+        invalid.extend_from_slice(b"\0\0\0\x04mdat");
+        assert!(!is_complete(&invalid, ImageFormat::Avif));
+    }
+}
@@ -1,30 +1,66 @@
 import { defineStore } from 'pinia'
 import { ref, computed } from 'vue'
 import { invoke } from '@tauri-apps/api/core'
-import type { AppSettings, FileItem, CompressResult } from '@/types'
+import type { AppSettings, FileItem, FilePlan, CompressResult, CompressError } from '@/types'
 
 export const useAppStore = defineStore('app', () => {
   const settings = ref<AppSettings>({
-    apiKey: '',
+    apiKeys: [],
+    apiEndpoint: 'https://api.tinify.com',
+    monthlyLimit: 500,
     notifyMode: 'notification',
     outputMode: 'alongside',
     outputDirectory: '',
     contextMenuEnabled: true,
     theme: 'auto',
+    backend: 'tinypng',
+    localPng: { maxColors: 256, quantizeSpeed: 10, optimizeLevel: 2 },
+    localJpeg: { quality: 75, chromaSubsampling: '420', progressive: true },
+    localWebp: { lossless: false, quality: 75, method: 4, alphaQuality: 100 },
+    localAvif: { quality: 70, speed: 6, alphaQuality: 80 },
+    convertTo: null,
+    convertBackground: 'white',
+    resize: null,
+    preserve: { copyright: false, creation: false, location: false },
+    retry: { maxAttempts: 3, baseDelayMs: 1000, maxDelayMs: 10000, jitter: true, retryStatuses: [408, 500, 502, 503, 504] },
+    proxy: { mode: 'system', url: '', username: '', password: '', noProxy: '' },
+    network: { connectTimeoutSecs: 30, timeoutSecs: 120, idleTimeoutSecs: 60, uploadLimitKbps: 0, downloadLimitKbps: 0 },
+    minSavings: { percent: 0, kb: 0 },
+    cacheEnabled: true,
+    markOutput: true,
   })
 
   const files = ref<FileItem[]>([])
   const isCompressing = ref(false)
+  const isPlanning = ref(false)
+  // 当前批次尚未开始的文件，取消时直接清空
+  let queue: FileItem[] = []
 
   const totalFiles = computed(() => files.value.length)
   const doneFiles = computed(() => files.value.filter(f => f.status === 'done').length)
+  const skippedFiles = computed(() => files.value.filter(f => f.status === 'skipped').length)
   const errorFiles = computed(() => files.value.filter(f => f.status === 'error').length)
+  // 「全部压缩」会处理的文件：后端判定原样重试不会成功的错误（格式不支持、Key 无效等）
+  // 不自动重跑，只能单独点重试
+  const pendingFiles = computed(() =>
+    files.value.filter(f => f.status === 'pending' || (f.status === 'error' && f.retryable))
+  )
   const totalSaved = computed(() => {
     return files.value
       .filter(f => f.status === 'done')
       .reduce((sum, f) => sum + (f.originalSize - f.compressedSize), 0)
   })
 
+  // 预估结果汇总：待压缩文件预计节省的空间和需要的额度
+  const plannedSaved = computed(() =>
+    files.value
+      .filter(f => f.status === 'pending' && (f.plan?.action === 'compress' || f.plan?.action === 'reuse'))
+      .reduce((sum, f) => sum + (f.plan!.input_size - (f.plan!.estimated_size ?? f.plan!.input_size)), 0)
+  )
+  const plannedQuota = computed(() =>
+    files.value.filter(f => f.status === 'pending' && f.plan?.uses_quota).length
+  )
+
   async function loadSettings() {
     try {
       const saved = await invoke<AppSettings>('load_settings')
@@ -43,11 +79,13 @@ export const useAppStore = defineStore('app', () => {
     }
   }
 
-  function addFiles(paths: string[]) {
-    const imageExts = ['.png', '.jpg', '.jpeg', '.webp']
-    for (const path of paths) {
-      const lower = path.toLowerCase()
-      if (!imageExts.some(ext => lower.endsWith(ext))) continue
+  // 由后端按文件内容筛选图片，扩展名缺失或有误的图片也能加入
+  async function addFiles(paths: string[]) {
+    const images = await invoke<string[]>('filter_images', { paths }).catch(() => {
+      const imageExts = ['.png', '.jpg', '.jpeg', '.webp', '.avif']
+      return paths.filter(p => imageExts.some(ext => p.toLowerCase().endsWith(ext)))
+    })
+    for (const path of images) {
       if (files.value.some(f => f.path === path)) continue
 
       const name = path.split(/[\\/]/).pop() ?? path
@@ -73,25 +111,28 @@ export const useAppStore = defineStore('app', () => {
   }
 
   async function compressAll() {
-    if (!settings.value.apiKey) {
+    if (settings.value.backend === 'tinypng' && !settings.value.apiKeys.some(k => k.key.trim())) {
       throw new Error('请先配置 API Key')
     }
 
-    const pending = files.value.filter(f => f.status === 'pending' || f.status === 'error')
+    const pending = pendingFiles.value
     if (pending.length === 0) return
 
     isCompressing.value = true
 
     // 并发队列：最多同时 3 个，剩余文件保持 pending 状态直到 worker 取到
     const CONCURRENCY = 3
-    const queue = [...pending]
+    queue = [...pending]
     const currentSettings = { ...settings.value }
 
     async function processOne(file: FileItem) {
       // 只有 worker 实际取到该文件时才更新为压缩中
       file.status = 'compressing'
+      file.backend = currentSettings.backend
+      file.plan = undefined
       file.progress = 0
       file.phase = undefined
+      file.retry = undefined
       file.errorMessage = undefined
       try {
         const result = await invoke<CompressResult>('compress_image', {
@@ -101,12 +142,31 @@ export const useAppStore = defineStore('app', () => {
         file.originalSize = result.input_size
         file.compressedSize = result.output_size
         file.outputPath = result.output_path
-        file.status = 'done'
+        file.outputFormat = result.format
+        file.apiKeyLabel = result.api_key_label ?? undefined
+        file.notice = result.extension_mismatch
+          ? `扩展名与实际格式不符，实为 ${result.input_format.toUpperCase()}`
+          : result.from_cache ? '复用之前的压缩结果，未消耗额度' : undefined
+        // 节省不足时未写入任何文件，原图保持不变
+        if (result.status === 'skipped') {
+          file.notice = result.skip_reason ?? undefined
+        }
+        file.status = result.status === 'skipped' ? 'skipped' : 'done'
         file.progress = 100
         file.phase = undefined
       } catch (e) {
+        const err = e as CompressError
+        // 被取消的文件回到待压缩状态，可再次开始
+        if (err?.kind === 'cancelled') {
+          file.status = 'pending'
+          file.progress = undefined
+          file.phase = undefined
+          return
+        }
         file.status = 'error'
-        file.errorMessage = String(e)
+        file.errorMessage = err?.message ?? String(e)
+        file.errorKind = err?.kind ?? 'other'
+        file.retryable = err?.retryable ?? false
         file.progress = undefined
         file.phase = undefined
       }
@@ -126,11 +186,13 @@ export const useAppStore = defineStore('app', () => {
     isCompressing.value = false
 
     const done = files.value.filter(f => f.status === 'done').length
+    const skipped = files.value.filter(f => f.status === 'skipped').length
     const errors = files.value.filter(f => f.status === 'error').length
     try {
       await invoke('notify_result', {
         settings: settings.value,
         successCount: done,
+        skippedCount: skipped,
         errorCount: errors,
       })
     } catch (e) {
@@ -138,12 +200,51 @@ export const useAppStore = defineStore('app', () => {
     }
   }
 
+  // 预估待压缩文件的处理方式和压缩效果，不访问 TinyPNG、不写入文件
+  async function planAll() {
+    const pending = pendingFiles.value
+    if (pending.length === 0) return
+
+    isPlanning.value = true
+    try {
+      const plans = await invoke<FilePlan[]>('plan_compression', {
+        filePaths: pending.map(f => f.path),
+        settings: settings.value,
+      })
+      for (const plan of plans) {
+        const file = pending.find(f => f.path === plan.path)
+        if (!file) continue
+        file.plan = plan
+        file.originalSize = plan.input_size
+      }
+    } finally {
+      isPlanning.value = false
+    }
+  }
+
+  // 取消单个文件：排队中的移出队列，压缩中的通知 Rust 中止
+  async function cancelFile(id: string) {
+    const file = files.value.find(f => f.id === id)
+    if (!file) return
+    queue = queue.filter(f => f.id !== id)
+    if (file.status === 'compressing') {
+      await invoke('cancel_compression', { path: file.path }).catch(console.error)
+    }
+  }
+
+  async function cancelAll() {
+    queue = []
+    await invoke('cancel_all').catch(console.error)
+  }
+
   async function retryFile(id: string) {
     const file = files.value.find(f => f.id === id)
     if (!file || file.status !== 'error') return
     // 重置为待压缩，复用 compressAll 的并发逻辑
     file.status = 'pending'
     file.errorMessage = undefined
+    file.errorKind = undefined
+    file.retryable = undefined
     file.progress = undefined
     file.phase = undefined
     await compressAll()
@@ -153,16 +254,24 @@ export const useAppStore = defineStore('app', () => {
     settings,
     files,
     isCompressing,
+    isPlanning,
     totalFiles,
     doneFiles,
+    skippedFiles,
     errorFiles,
+    pendingFiles,
     totalSaved,
+    plannedSaved,
+    plannedQuota,
     loadSettings,
     saveSettings,
     addFiles,
     clearFiles,
     removeFile,
     compressAll,
+    planAll,
     retryFile,
+    cancelFile,
+    cancelAll,
   }
 })
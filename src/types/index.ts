@@ -1,18 +1,116 @@
 export type NotifyMode = 'dialog' | 'notification' | 'silent'
 export type OutputMode = 'alongside' | 'overwrite' | 'directory'
 export type Theme = 'auto' | 'light' | 'dark'
+export type ImageFormat = 'png' | 'jpeg' | 'webp' | 'avif'
+export type ResizeMethod = 'scale' | 'fit' | 'cover' | 'thumb'
+
+export interface ResizeSpec {
+  method: ResizeMethod
+  width: number | null
+  height: number | null
+}
+
+export type MetadataField = 'copyright' | 'creation' | 'location'
+
+export interface PreserveSettings {
+  copyright: boolean
+  creation: boolean
+  location: boolean
+}
+
+export type BackendKind = 'tinypng' | 'local'
+
+export interface LocalPngSettings {
+  maxColors: number
+  quantizeSpeed: number
+  optimizeLevel: number
+}
+
+export type ChromaSubsampling = '420' | '422' | '444'
+
+export interface LocalJpegSettings {
+  quality: number
+  chromaSubsampling: ChromaSubsampling
+  progressive: boolean
+}
+
+export interface LocalWebpSettings {
+  lossless: boolean
+  quality: number
+  method: number
+  alphaQuality: number
+}
+
+export interface LocalAvifSettings {
+  quality: number
+  speed: number
+  alphaQuality: number
+}
+
+export interface RetrySettings {
+  maxAttempts: number
+  baseDelayMs: number
+  maxDelayMs: number
+  jitter: boolean
+  retryStatuses: number[]
+}
+
+export type ProxyMode = 'system' | 'none' | 'manual'
+
+export interface ProxySettings {
+  mode: ProxyMode
+  url: string
+  username: string
+  password: string
+  noProxy: string
+}
+
+export interface NetworkSettings {
+  connectTimeoutSecs: number
+  timeoutSecs: number
+  idleTimeoutSecs: number
+  uploadLimitKbps: number
+  downloadLimitKbps: number
+}
+
+export interface MinSavingsSettings {
+  percent: number   // 最少节省比例（%），0 表示不限制
+  kb: number        // 最少节省体积（KB），0 表示不限制
+}
+
+export interface ApiKey {
+  label: string
+  key: string
+}
 
 export interface AppSettings {
-  apiKey: string
+  apiKeys: ApiKey[]
+  apiEndpoint: string
+  monthlyLimit: number
   notifyMode: NotifyMode
   outputMode: OutputMode
   outputDirectory: string
   contextMenuEnabled: boolean
   theme: Theme
+  backend: BackendKind
+  localPng: LocalPngSettings
+  localJpeg: LocalJpegSettings
+  localWebp: LocalWebpSettings
+  localAvif: LocalAvifSettings
+  convertTo: ImageFormat | null
+  convertBackground: string
+  resize: ResizeSpec | null
+  preserve: PreserveSettings
+  retry: RetrySettings
+  proxy: ProxySettings
+  network: NetworkSettings
+  minSavings: MinSavingsSettings
+  cacheEnabled: boolean
+  markOutput: boolean
 }
 
-export type FileStatus = 'pending' | 'compressing' | 'done' | 'error'
-export type CompressPhase = 'uploading' | 'processing' | 'downloading'
+export type FileStatus = 'pending' | 'compressing' | 'done' | 'skipped' | 'error'
+export type CompressPhase = 'uploading' | 'processing' | 'downloading' | 'retrying'
 
 export interface FileItem {
   id: string
@@ -22,13 +120,98 @@ export interface FileItem {
   compressedSize: number
   status: FileStatus
   errorMessage?: string
+  errorKind?: CompressErrorKind
+  retryable?: boolean     // 失败时后端判定原样重试是否可能成功
   outputPath?: string
+  outputFormat?: ImageFormat
+  apiKeyLabel?: string    // 压缩所用 API Key 的备注
+  notice?: string         // 附加提示，如扩展名与实际格式不符、跳过原因
+  plan?: FilePlan         // 预估结果，开始压缩后清除
   progress?: number       // 0-100，压缩中时实时更新
   phase?: CompressPhase   // 当前阶段
+  backend?: BackendKind   // 压缩所用后端，决定阶段提示文案
+  retry?: { attempt: number; delayMs: number }  // 重试阶段：第几次重试、等待毫秒数
 }
 
+export type CompressStatus = 'compressed' | 'skipped'
+
 export interface CompressResult {
+  status: CompressStatus
+  skip_reason: string | null   // 跳过的原因（节省不足、已压缩过等），原图未被改动
   input_size: number
   output_size: number
   output_path: string
+  backend: string
+  input_format: ImageFormat
+  extension_mismatch: boolean  // 扩展名与实际格式不符或没有扩展名
+  format: ImageFormat
+  width: number | null
+  height: number | null
+  preserved: MetadataField[]
+  compression_count: number | null
+  api_key_label: string | null
+  from_cache: boolean  // 复用之前的压缩结果或识别为已压缩，未消耗额度
+}
+
+export type PlanAction = 'compress' | 'reuse' | 'skip' | 'error'
+
+// plan_compression 返回的单个文件预估，不访问 TinyPNG、不写入文件
+export interface FilePlan {
+  path: string
+  action: PlanAction
+  reason: string | null          // 跳过或无法压缩的原因
+  input_size: number
+  input_format: ImageFormat | null
+  extension_mismatch: boolean
+  output_path: string | null
+  output_format: ImageFormat | null
+  width: number | null
+  height: number | null
+  estimated_size: number | null  // 本地估算值；复用缓存时为之前输出的实际大小
+  conflicts: string[]            // 会覆盖已有文件、与同批文件输出到同一位置等
+  uses_quota: boolean            // 是否会消耗 TinyPNG 额度
+}
+
+export type CompressErrorKind =
+  | 'unauthorized'
+  | 'quotaExceeded'
+  | 'unsupportedFormat'
+  | 'fileTooLarge'
+  | 'fileRead'
+  | 'network'
+  | 'server'
+  | 'diskWrite'
+  | 'corruptDownload'
+  | 'verifyFailed'
+  | 'invalidSettings'
+  | 'cancelled'
+  | 'other'
+
+// compress_image 命令失败时返回的错误
+export interface CompressError {
+  kind: CompressErrorKind
+  message: string
+  retryable: boolean   // 不改设置、原样重试是否可能成功
+}
+
+export type KeyState = 'valid' | 'invalid' | 'limitReached' | 'networkError'
+
+export interface KeyStatus {
+  state: KeyState
+  compressionCount: number | null
+  message: string
+}
+
+// 本月压缩次数接近限额时后端发出的 quota-warning 事件
+export interface QuotaWarning {
+  label: string
+  count: number
+  limit: number
+}
+
+export interface CompressionUsage {
+  month: string
+  count: number
+  limit: number
+  exhausted: boolean
 }